@@ -0,0 +1,10 @@
+<?xml version="1.0" encoding="UTF-8"?>
+<tileset version="1.2" name="external" tilewidth="16" tileheight="16" tilecount="816" columns="16">
+ <properties>
+  <property name="biome" value="forest"/>
+ </properties>
+ <image source="../tileset.png" width="256" height="816"/>
+ <tile id="3">
+  <image source="extra/rock.png"/>
+ </tile>
+</tileset>
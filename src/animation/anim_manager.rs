@@ -1,12 +1,25 @@
+use std::collections::HashMap;
+
 use sprite::SpriteManager;
-use animation::{Animation, AnimFrame, AnimData};
+use animation::{Animation, AnimFrame, AnimData, LoopMode};
+
+// seconds, for frames without a duration
+const DEFAULT_FRAME_DURATION: f32 = 0.2;
 
 pub struct AnimManager {
     anims: Vec<Animation>,
+    indices: HashMap<String, usize>,
     timer: f32,
     anim_running: bool,
     current_anim: usize,
-    current_frame: usize
+    current_frame: usize,
+    // 1 or -1, only ping-pong animations play backwards,
+    // 0 once a hold-last animation reached its end
+    step: isize,
+    // the current frame's event is still to be sent
+    entered: bool,
+    events: Vec<String>,
+    finished: bool
 }
 
 impl AnimManager {
@@ -14,21 +27,39 @@ impl AnimManager {
         let mut anims = Vec::new();
         for anim_data in data.iter() {
             let mut anim = Animation::new(&anim_data.name);
+            anim.mode = LoopMode::from_name(anim_data.loop_mode.as_deref());
+
             for anim_frame_data in anim_data.frames.iter() {
                 if let Some(index) = sprites.find_index(&anim_frame_data.name) {
-                    let frame = AnimFrame::new(index, 0.2);
+                    let mut frame = AnimFrame::new(index, anim_frame_data.duration.unwrap_or(DEFAULT_FRAME_DURATION));
+                    frame.event = anim_frame_data.event.clone();
                     anim.frames.push(frame);
                 }
             }
             anims.push(anim);
         }
 
+        AnimManager::with_anims(anims)
+    }
+
+    fn with_anims(anims: Vec<Animation>) -> AnimManager {
+        let mut indices = HashMap::new();
+        for (i, anim) in anims.iter().enumerate() {
+            // the first of several with the same name wins
+            indices.entry(anim.name.clone()).or_insert(i);
+        }
+
         AnimManager {
             anims: anims,
+            indices,
             timer: 0.0,
             anim_running: false,
             current_anim: 0,
-            current_frame: 0
+            current_frame: 0,
+            step: 1,
+            entered: false,
+            events: Vec::new(),
+            finished: false
         }
     }
 
@@ -36,7 +67,11 @@ impl AnimManager {
         self.anims[self.current_anim].frames[self.current_frame].index
     }
 
+    // clears the events and the finished flag of the last update
     pub fn update(&mut self, dt: f32) {
+        self.events.clear();
+        self.finished = false;
+
         if !self.anim_running {
             return;
         }
@@ -44,43 +79,104 @@ impl AnimManager {
         self.advance_anim(dt);
     }
 
-    pub fn run(&mut self, name: &str) {
-        if self.anim_running && self.anims[self.current_anim].name == name {
-            return;
+    // index from find_anim, an animation already running isn't restarted.
+    // false if it has no frames
+    pub fn play(&mut self, index: usize) -> bool {
+        if self.anim_running && self.current_anim == index {
+            return true;
         }
 
-        if let Some(index) = self.find_anim(name) {
-            self.anim_running = true;
-            self.timer = 0.0;
-            self.current_anim = index;
-            self.current_frame = 0;
-        } else {
-            println!("animation not found: {}", name);
+        if self.anims[index].frames.is_empty() {
+            println!("animation has no frames: {}", self.anims[index].name);
+            return false;
         }
+
+        self.anim_running = true;
+        self.timer = 0.0;
+        self.current_anim = index;
+        self.current_frame = 0;
+        self.step = 1;
+        self.entered = true;
+        true
     }
 
     pub fn stop_anim(&mut self) {
         self.anim_running = false;
     }
 
-    pub fn anim_running(&self) -> bool {
-        self.anim_running
+    // of the frames started during the last update, in order
+    pub fn events(&self) -> &[String] {
+        &self.events
+    }
+
+    // a once or hold-last animation ended during the last update
+    pub fn finished(&self) -> bool {
+        self.finished
     }
 
     fn advance_anim(&mut self, dt: f32) {
         self.timer += dt;
 
-        if self.timer > self.get_delay(self.current_anim, self.current_frame) {
+        loop {
+            if self.entered {
+                self.entered = false;
+                let frame = &self.anims[self.current_anim].frames[self.current_frame];
+                if let Some(ref event) = frame.event {
+                    self.events.push(event.clone());
+                }
+            }
+
+            let delay = self.get_delay(self.current_anim, self.current_frame);
+            // zero length frames would never let the loop end
+            if self.timer < delay || delay <= 0.0 || !self.anim_running {
+                break;
+            }
+
+            self.timer -= delay;
             self.next_frame();
         }
     }
 
     fn next_frame(&mut self) {
-        self.timer = 0.0;
-        self.current_frame += 1;
+        // holding the last frame
+        if self.step == 0 {
+            self.timer = 0.0;
+            return;
+        }
+
+        let (mode, len) = {
+            let anim = &self.anims[self.current_anim];
+            (anim.mode, anim.frames.len() as isize)
+        };
+
+        let next = self.current_frame as isize + self.step;
+        if next >= 0 && next < len {
+            self.current_frame = next as usize;
+            self.entered = true;
+            return;
+        }
 
-        if self.current_frame >= self.anims[self.current_anim].frames.len() {
-            self.current_frame = 0;
+        match mode {
+            LoopMode::Loop => {
+                self.current_frame = 0;
+                self.entered = true;
+            },
+            LoopMode::PingPong => {
+                self.step = -self.step;
+                if len > 1 {
+                    self.current_frame = (self.current_frame as isize + self.step) as usize;
+                    self.entered = true;
+                }
+            },
+            LoopMode::Once => {
+                self.anim_running = false;
+                self.finished = true;
+            },
+            LoopMode::HoldLast => {
+                self.timer = 0.0;
+                self.step = 0;
+                self.finished = true;
+            }
         }
     }
 
@@ -88,13 +184,89 @@ impl AnimManager {
         self.anims[anim].frames[frame].delay
     }
 
-    fn find_anim(&self, name: &str) -> Option<usize> {
-        for (i, anim) in self.anims.iter().enumerate() {
-            if anim.name == name {
-                return Some(i);
-            }
+    pub fn find_anim(&self, name: &str) -> Option<usize> {
+        self.indices.get(name).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use animation::{Animation, AnimFrame, LoopMode};
+    use super::AnimManager;
+
+    fn anim(name: &str, mode: LoopMode, frames: usize) -> Animation {
+        let mut anim = Animation::new(name);
+        anim.mode = mode;
+        for i in 0..frames {
+            let mut frame = AnimFrame::new(i, 0.1);
+            frame.event = Some(format!("frame{}", i));
+            anim.frames.push(frame);
         }
-        
-        None
+        anim
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn ping_pong() {
+        let mut am = AnimManager::with_anims(vec![anim("wave", LoopMode::PingPong, 3)]);
+        am.play(0);
+
+        let mut shown = Vec::new();
+        let mut events = Vec::new();
+        for _ in 0..6 {
+            am.update(0.1);
+            shown.push(am.current());
+            events.extend(am.events().iter().cloned());
+        }
+
+        assert_eq!(shown, vec![1, 2, 1, 0, 1, 2]);
+        assert_eq!(events, vec!["frame0", "frame1", "frame2", "frame1", "frame0", "frame1", "frame2"]);
+    }
+
+    #[test]
+    fn once_and_hold_last_finish() {
+        let mut am = AnimManager::with_anims(vec![anim("attack", LoopMode::Once, 2),
+                                                  anim("emote", LoopMode::HoldLast, 2)]);
+        am.play(0);
+        am.update(0.15);
+        assert!(!am.finished());
+        am.update(0.1);
+        assert!(am.finished());
+        // a stopped animation stays on its last frame
+        am.update(0.1);
+        assert!(!am.finished());
+        assert_eq!(am.current(), 1);
+
+        am.play(1);
+        am.update(0.25);
+        assert!(am.finished());
+        assert_eq!(am.current(), 1);
+        am.update(0.5);
+        assert!(!am.finished() && am.events().is_empty());
+        assert_eq!(am.current(), 1);
+    }
+
+    #[test]
+    fn frame_at_matches_playback() {
+        let mut walk = Animation::new("walk");
+        for (i, &delay) in [0.1, 0.25, 0.05].iter().enumerate() {
+            walk.frames.push(AnimFrame::new(i, delay));
+        }
+        let expected: Vec<usize> = (1..40).map(|i| walk.frame_at(i as f32 * 0.07).unwrap().index).collect();
+
+        let mut am = AnimManager::with_anims(vec![walk]);
+        am.play(0);
+        let shown: Vec<usize> = (1..40).map(|_| { am.update(0.07); am.current() }).collect();
+
+        assert_eq!(shown, expected);
+    }
+
+    #[test]
+    fn first_name_wins() {
+        let am = AnimManager::with_anims(vec![anim("walk", LoopMode::Loop, 2),
+                                              anim("run", LoopMode::Loop, 2),
+                                              anim("walk", LoopMode::Once, 3)]);
+        assert_eq!(am.find_anim("walk"), Some(0));
+        assert_eq!(am.find_anim("run"), Some(1));
+        assert!(am.find_anim("jump").is_none());
+    }
+}
@@ -1,41 +1,132 @@
 mod anim_manager;
 pub type AnimManager = anim_manager::AnimManager;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AnimData {
     name: String,
+    // "loop" (default), "once", "ping-pong" or "hold-last"
+    #[serde(rename = "loop")]
+    loop_mode: Option<String>,
     frames: Vec<AnimFrameData>
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 struct AnimFrameData {
-    name: String
+    name: String,
+    // seconds
+    duration: Option<f32>,
+    // sent to the owner when the frame starts
+    event: Option<String>
+}
+
+impl AnimData {
+    pub fn new(name: &str, loop_mode: &str) -> AnimData {
+        AnimData {
+            name: String::from(name),
+            loop_mode: Some(String::from(loop_mode)),
+            frames: Vec::new()
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn set_name(&mut self, name: &str) {
+        self.name = String::from(name);
+    }
+
+    pub fn push_frame(&mut self, sprite: &str, duration: f32) {
+        self.frames.push(AnimFrameData {
+            name: String::from(sprite),
+            duration: Some(duration),
+            event: None
+        });
+    }
+
+    pub fn sprite_names(&self) -> Vec<&str> {
+        self.frames.iter().map(|f| f.name.as_str()).collect()
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum LoopMode {
+    Loop,
+    // stops the animation
+    Once,
+    // plays backwards, then forwards again
+    PingPong,
+    // keeps showing the last frame
+    HoldLast,
+}
+
+impl LoopMode {
+    pub fn from_name(name: Option<&str>) -> LoopMode {
+        match name {
+            Some("once") => LoopMode::Once,
+            Some("ping-pong") => LoopMode::PingPong,
+            Some("hold-last") => LoopMode::HoldLast,
+            Some("loop") | None => LoopMode::Loop,
+            Some(other) => {
+                println!("unknown loop mode {}, looping", other);
+                LoopMode::Loop
+            }
+        }
+    }
 }
 
 pub struct Animation {
     pub name: String,
+    pub mode: LoopMode,
     pub frames: Vec<AnimFrame>
 }
 
 pub struct AnimFrame {
     pub index: usize,
-    pub delay: f32
+    pub delay: f32,
+    pub event: Option<String>
 }
 
 impl Animation {
     pub fn new(name: &str) -> Animation {
         Animation {
             name: String::from(name),
+            mode: LoopMode::Loop,
             frames: Vec::new()
         }
     }
+
+    pub fn duration(&self) -> f32 {
+        self.frames.iter().map(|f| f.delay).sum()
+    }
+
+    // time in seconds, loops
+    pub fn frame_at(&self, time: f32) -> Option<&AnimFrame> {
+        self.frame_index_at(time).map(|i| &self.frames[i])
+    }
+
+    pub fn frame_index_at(&self, time: f32) -> Option<usize> {
+        if self.frames.is_empty() { return None; }
+
+        let duration = self.duration();
+        if duration <= 0.0 { return Some(0); }
+
+        let mut t = time % duration;
+        for (i, frame) in self.frames.iter().enumerate() {
+            if t < frame.delay { return Some(i); }
+            t -= frame.delay;
+        }
+
+        Some(self.frames.len() - 1)
+    }
 }
 
 impl AnimFrame {
     pub fn new(index: usize, delay: f32) -> AnimFrame {
         AnimFrame {
             index: index,
-            delay: delay
+            delay,
+            event: None
         }
     }
 }
@@ -1,4 +1,4 @@
-use types::{Vec2, Size, Point};
+use types::{Vec2, Size, Point, Rect};
 use cgmath::EuclideanSpace;
 
 const THRESHOLD: f32 = 0.8;
@@ -24,10 +24,21 @@ impl Camera {
         self.target = target;
     } 
 
+    // jumps instead of scrolling
+    pub fn snap_to(&mut self, target: Point) {
+        self.target = target;
+        self.pos = target + (self.screen.to_point() * -0.5).to_vec();
+    }
+
     pub fn next_vec(&self, a: f32) -> Vec2 {
         self.next_pos(a).to_vec() * -1.0
     }
 
+    pub fn view(&self, a: f32) -> Rect {
+        let pos = self.next_pos(a);
+        Rect::new(pos.x, pos.y, self.screen.w, self.screen.h)
+    }
+
     fn next_pos(&self, a: f32) -> Point {
         let target = self.target + (self.screen.to_point() * -0.5).to_vec();
         let dist_x = target.x - self.pos.x;
@@ -4,6 +4,9 @@ use std::rc::Rc;
 
 use sdl2::EventPump as SdlEvents;
 use sdl2::render::Renderer as SdlRenderer;
+use sdl2::render::BlendMode;
+use sdl2::surface::Surface as SdlSurface;
+use sdl2::pixels::PixelFormatEnum;
 use sdl2::image::{INIT_PNG, LoadTexture};
 use sdl2::event::Event::*;
 use sdl2::keyboard::Keycode::*;
@@ -13,60 +16,144 @@ use time::{Duration, PreciseTime};
 use scene::Scene;
 use camera::Camera;
 use sprite::SpriteCache;
-use types::{KeyAction, Point, Size, Vec2, RenderInfo, Renderable, Color, Texture, to_sdl_rect};
+use types::{KeyAction, Point, Size, Rect, Vec2, RenderInfo, Renderable, Transform, Blend, Color, Texture, to_sdl_rect};
 
 const CAMERA_SPEED: f32 = 2.0;
 const WINDOW_W: u32 = 1920;
 const WINDOW_H: u32 = 1200;
+const WINDOW_TITLE: &str = "n3-ctx";
 
 const NANOS_IN_SECOND: f32 = 1000000000.0;
 const STEP_NS: f32 = NANOS_IN_SECOND / 60.0;
 
-pub struct Context<'renderer> {
-    running: bool,
-    events: SdlEvents,
-    renderer: SdlRenderer<'renderer>,
-    sprite_cache: SpriteCache,
-    camera: Camera,
-    held_keys: Vec<KeyAction>,
-    render_buffer: Vec<RenderInfo>
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum DisplayMode {
+    Windowed,
+    FullscreenDesktop,
+    // renders into an offscreen software surface, no window or gpu needed
+    Headless,
 }
 
-impl<'renderer> Context<'renderer> {
-    pub fn new() -> Context<'renderer> {
-        let sdl_context = super::sdl2::init().unwrap();
-        let video = sdl_context.video().unwrap();
-        let _image_context = super::sdl2::image::init(INIT_PNG).unwrap();
+// tile layers cull whole chunks before submitting them,
+// everything else is culled item by item when presenting
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RenderStats {
+    pub drawn: usize,
+    pub chunks_culled: usize,
+    pub items_culled: usize,
+}
+
+pub struct ContextBuilder {
+    width: u32,
+    height: u32,
+    mode: DisplayMode,
+}
+
+impl ContextBuilder {
+    pub fn new() -> ContextBuilder {
+        ContextBuilder {
+            width: WINDOW_W,
+            height: WINDOW_H,
+            mode: DisplayMode::FullscreenDesktop
+        }
+    }
+
+    pub fn size(mut self, width: u32, height: u32) -> ContextBuilder {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    pub fn windowed(mut self) -> ContextBuilder {
+        self.mode = DisplayMode::Windowed;
+        self
+    }
 
-        let window = video.window("n3-ctx", WINDOW_W, WINDOW_H)
-            .fullscreen_desktop()
-            .build().unwrap();
+    pub fn headless(mut self) -> ContextBuilder {
+        self.mode = DisplayMode::Headless;
+        self
+    }
+
+    pub fn build<'renderer>(self) -> Result<Context<'renderer>, String> {
+        let sdl_context = super::sdl2::init()?;
+        let _image_context = super::sdl2::image::init(INIT_PNG)?;
+
+        let renderer = match self.mode {
+            DisplayMode::Headless => {
+                let surface = SdlSurface::new(self.width, self.height, PixelFormatEnum::RGBA8888)?;
+                SdlRenderer::from_surface(surface)?
+            },
+            DisplayMode::Windowed | DisplayMode::FullscreenDesktop => {
+                let video = sdl_context.video()?;
+                let mut wb = video.window(WINDOW_TITLE, self.width, self.height);
+                if self.mode == DisplayMode::FullscreenDesktop {
+                    wb.fullscreen_desktop();
+                }
+
+                let window = wb.build().map_err(|e| e.to_string())?;
+                window.renderer().accelerated().target_texture()
+                    .build().map_err(|e| e.to_string())?
+            }
+        };
 
         let sc = SpriteCache::new();
         let c = Camera::new(Point::new(0.0, 0.0),
-                            Size::new(WINDOW_W as f32, WINDOW_H as f32),
+                            Size::new(self.width as f32, self.height as f32),
                             CAMERA_SPEED);
 
-        Context {
+        Ok(Context {
             running: false,
-            events: sdl_context.event_pump().unwrap(),
-            renderer: window.renderer().accelerated().build().unwrap(),
+            mode: self.mode,
+            screen: Size::new(self.width as f32, self.height as f32),
+            events: sdl_context.event_pump()?,
+            renderer,
             sprite_cache: sc,
             camera: c,
+            view: Rect::new(0.0, 0.0, self.width as f32, self.height as f32),
             held_keys: Vec::new(),
-            render_buffer: Vec::new()
-        }
+            render_buffer: Vec::new(),
+            stats: RenderStats::default(),
+            last_stats: RenderStats::default()
+        })
+    }
+}
+
+pub struct Context<'renderer> {
+    running: bool,
+    mode: DisplayMode,
+    screen: Size,
+    events: SdlEvents,
+    renderer: SdlRenderer<'renderer>,
+    sprite_cache: SpriteCache,
+    camera: Camera,
+    view: Rect,
+    held_keys: Vec<KeyAction>,
+    render_buffer: Vec<RenderInfo>,
+    stats: RenderStats,
+    last_stats: RenderStats
+}
+
+impl<'renderer> Context<'renderer> {
+    pub fn builder() -> ContextBuilder {
+        ContextBuilder::new()
     }
 
-    pub fn run_scene<T>(&mut self, s: &mut T) where T: Scene {
+    // with a frame count every frame advances one fixed step,
+    // so headless output is reproducible
+    pub fn run_scene<T>(&mut self, s: &mut T, frames: Option<u32>) where T: Scene {
         self.running = true;
         let mut current_time = PreciseTime::now();
         let step = Duration::nanoseconds(STEP_NS.floor() as i64);
         let dt = step.num_nanoseconds().unwrap() as f32 / NANOS_IN_SECOND;
         let max_frame_time = Duration::seconds(1);
         let mut accumulator = Duration::zero();
+        let mut frame = 0;
 
         while self.running {
+            if let Some(max) = frames {
+                if frame >= max { break; }
+            }
+
             self.handle_events();
 
             let new_time = PreciseTime::now();
@@ -76,6 +163,10 @@ impl<'renderer> Context<'renderer> {
             if frame_time > max_frame_time {
                 frame_time = max_frame_time;
             }
+
+            if frames.is_some() {
+                frame_time = step;
+            }
             
             accumulator = accumulator + frame_time;
 
@@ -89,30 +180,53 @@ impl<'renderer> Context<'renderer> {
             self.renderer.set_draw_color(Color::RGB(0, 0, 0));
             self.renderer.clear();
 
-            let a = (current_time.to(PreciseTime::now()).num_nanoseconds().unwrap() as f32 / NANOS_IN_SECOND) / dt;
+            let a = match frames {
+                Some(_) => 0.0,
+                None => (current_time.to(PreciseTime::now()).num_nanoseconds().unwrap() as f32 / NANOS_IN_SECOND) / dt
+            };
 
+            self.view = self.camera.view(a);
             s.draw(self, a);
 
             self.present(a);
             self.renderer.present();
+            frame += 1;
         }
+
+        self.running = false;
+    }
+
+    pub fn is_headless(&self) -> bool {
+        self.mode == DisplayMode::Headless
+    }
+
+    pub fn screen_size(&self) -> Size {
+        self.screen
+    }
+
+    // RGBA8888, rows of width * 4 bytes
+    pub fn read_pixels(&self) -> Result<Vec<u8>, String> {
+        self.renderer.read_pixels(None, PixelFormatEnum::RGBA8888)
     }
 
     pub fn load_sheet(&mut self, name: &str) {
         self.sprite_cache.load_sheet(name, &mut self.renderer);
     }
 
-    pub fn load_texture(&mut self, name: &str) -> Rc<RefCell<Texture>> {
+    // freed once the last clone of the handle is dropped
+    pub fn load_texture(&mut self, name: &str) -> Result<Rc<RefCell<Texture>>, String> {
         let path = String::from("assets/") + name;
-        let tex = self.renderer.load_texture(Path::new(&path)).unwrap();
-        Rc::new(RefCell::new(tex))
+        match self.renderer.load_texture(Path::new(&path)) {
+            Ok(tex) => Ok(Rc::new(RefCell::new(tex))),
+            Err(err) => Err(format!("Error while loading texture {}: {}", path, err))
+        }
     }
 
     pub fn get_sprite_cache(&self) -> &SpriteCache {
         &self.sprite_cache
     }
 
-    pub fn held_keys(&self) -> super::std::slice::Iter<KeyAction> {
+    pub fn held_keys(&self) -> super::std::slice::Iter<'_, KeyAction> {
         self.held_keys.iter()
     }
 
@@ -120,39 +234,91 @@ impl<'renderer> Context<'renderer> {
         self.held_keys.last()
     }
 
+    pub fn press_key(&mut self, key: KeyAction) {
+        if !self.held_keys.contains(&key) {
+            self.held_keys.push(key);
+        }
+    }
+
+    pub fn release_key(&mut self, key: KeyAction) {
+        self.held_keys.retain(|&x| x != key);
+    }
+
     pub fn set_camera_target(&mut self, t: Point) {
         self.camera.set_target(t);
     }
 
+    pub fn snap_camera(&mut self, t: Point) {
+        self.camera.snap_to(t);
+    }
+
+    // scaling the camera position makes layers with parallax below 1
+    // scroll slower than the world and above 1 faster
+    pub fn layer_view(&self, parallax: Vec2) -> Rect {
+        parallax_view(self.view, parallax)
+    }
+
     pub fn render(&mut self, r: RenderInfo) {
         self.render_buffer.push(r);
     }
 
+    pub fn add_culled_chunks(&mut self, count: usize) {
+        self.stats.chunks_culled += count;
+    }
+
+    pub fn render_stats(&self) -> RenderStats {
+        self.last_stats
+    }
+
     fn present(&mut self, a: f32) {
-        self.render_buffer.sort_by_key(|e| e.z as i32);;
+        let view = self.view;
+        let submitted = self.render_buffer.len();
+        self.render_buffer.retain(|r| r.bounds().intersects(&parallax_view(view, r.parallax)));
+
+        self.stats.items_culled += submitted - self.render_buffer.len();
+        self.stats.drawn = self.render_buffer.len();
+        self.last_stats = self.stats;
+        self.stats = RenderStats::default();
+
+        // stable, so equal depths keep their submission order
+        self.render_buffer.sort_by(|a, b| a.depth.cmp(&b.depth));
         let camera_offset = self.camera.next_vec(a);
 
-        for r in &self.render_buffer {
-            use std::ops::DerefMut;
-            match r.renderable {
-                Renderable::Texture { src, src_size, ref tex } => {
-                    copy_texture(&mut self.renderer,
-                                 camera_offset,
-                                 r.pos, r.size,
-                                 src, src_size,
-                                 tex.borrow_mut().deref_mut());
-                },
-                Renderable::Rect { color } => render_rect(&mut self.renderer,
-                                                          camera_offset,
-                                                          r.pos, r.size, color),
-            }
+        draw_all(&mut self.renderer, camera_offset, &self.render_buffer, None);
+        self.render_buffer.clear();
+    }
+
+    pub fn can_bake(&self) -> bool {
+        self.renderer.render_target_supported()
+    }
+
+    // blending is off, so items are copied as they are and must not
+    // overlap. None without render target support
+    pub fn bake(&mut self, size: Size, origin: Point, items: &[RenderInfo]) -> Option<Rc<RefCell<Texture>>> {
+        if !self.can_bake() { return None; }
+
+        match self.renderer.render_target() {
+            Some(mut target) => {
+                let created = target.create_and_set(PixelFormatEnum::RGBA8888,
+                                                    size.w as u32, size.h as u32);
+                if created.is_err() { return None; }
+            },
+            None => return None
         }
 
-        self.render_buffer.clear();
+        self.renderer.set_draw_color(Color::RGBA(0, 0, 0, 0));
+        self.renderer.clear();
+        draw_all(&mut self.renderer, Vec2::new(-origin.x, -origin.y), items, Some(BlendMode::None));
+
+        match self.renderer.render_target().map(|mut t| t.reset()) {
+            Some(Ok(Some(tex))) => Some(Rc::new(RefCell::new(tex))),
+            _ => None
+        }
     }
 
     fn handle_events(&mut self) {
-        for event in self.events.poll_iter() {
+        let events: Vec<_> = self.events.poll_iter().collect();
+        for event in events {
             match event {
                 Quit { .. } => self.running = false,
                 KeyDown { keycode, repeat, .. } => {
@@ -162,20 +328,20 @@ impl<'renderer> Context<'renderer> {
 
                     match keycode {
                         Some(Escape) => self.running = false,
-                        Some(W) => self.held_keys.push(KeyAction::Up),
-                        Some(A) => self.held_keys.push(KeyAction::Left),
-                        Some(S) => self.held_keys.push(KeyAction::Down),
-                        Some(D) => self.held_keys.push(KeyAction::Right),
+                        Some(W) => self.press_key(KeyAction::Up),
+                        Some(A) => self.press_key(KeyAction::Left),
+                        Some(S) => self.press_key(KeyAction::Down),
+                        Some(D) => self.press_key(KeyAction::Right),
                         // Some(R) => self.
                         _ => {}
                     }
                 }
                 KeyUp { keycode, .. } => {
                     match keycode {
-                        Some(W) => self.held_keys.retain(|&x| x != KeyAction::Up),
-                        Some(A) => self.held_keys.retain(|&x| x != KeyAction::Left),
-                        Some(S) => self.held_keys.retain(|&x| x != KeyAction::Down),
-                        Some(D) => self.held_keys.retain(|&x| x != KeyAction::Right),
+                        Some(W) => self.release_key(KeyAction::Up),
+                        Some(A) => self.release_key(KeyAction::Left),
+                        Some(S) => self.release_key(KeyAction::Down),
+                        Some(D) => self.release_key(KeyAction::Right),
                         _ => {}
                     }
                 }
@@ -185,17 +351,118 @@ impl<'renderer> Context<'renderer> {
     }
 }
 
-fn copy_texture(r: &mut SdlRenderer, camera_offset: Vec2, pos: Point, size: Size, src: Point, src_size: Size, tex: &mut Texture) {
-    let dest = pos + camera_offset;
-    let _ = r.copy(tex,
-                 Some(to_sdl_rect(src, src_size)),
-                 Some(to_sdl_rect(dest, size)));
+fn parallax_view(view: Rect, parallax: Vec2) -> Rect {
+    Rect::new(view.pos.x * parallax.x, view.pos.y * parallax.y, view.size.w, view.size.h)
+}
+
+// blend overrides the blend mode of every item
+fn draw_all(r: &mut SdlRenderer, offset: Vec2, items: &[RenderInfo], blend: Option<BlendMode>) {
+    use std::ops::DerefMut;
+    for item in items {
+        let offset = Vec2::new(offset.x * item.parallax.x, offset.y * item.parallax.y);
+        let mode = blend.unwrap_or(blend_mode(item.blend));
+        match item.renderable {
+            Renderable::Texture { src, src_size, ref tex } => {
+                let mut tex = tex.borrow_mut();
+                let (red, green, blue) = item.color_mod.rgb();
+                tex.set_alpha_mod(item.alpha);
+                tex.set_color_mod(red, green, blue);
+                tex.set_blend_mode(mode);
+                copy_texture(r,
+                             item.pos + offset, item.size,
+                             src, src_size,
+                             item.transform,
+                             tex.deref_mut());
+            },
+            Renderable::Rect { color } => {
+                let (red, green, blue, alpha) = color.rgba();
+                let alpha = ((alpha as u16 * item.alpha as u16) / 255) as u8;
+                r.set_blend_mode(mode);
+                render_rect(r, offset, item.pos, item.size,
+                            Color::RGBA(red, green, blue, alpha));
+                r.set_blend_mode(BlendMode::None);
+            },
+        }
+    }
+}
+
+fn blend_mode(blend: Blend) -> BlendMode {
+    match blend {
+        Blend::Alpha => BlendMode::Blend,
+        Blend::Additive => BlendMode::Add,
+        Blend::Multiply => BlendMode::Mod,
+    }
+}
+
+fn copy_texture(r: &mut SdlRenderer, dest: Point, size: Size, src: Point, src_size: Size, t: Transform, tex: &mut Texture) {
+    if t.is_identity() {
+        let _ = r.copy(tex,
+                     Some(to_sdl_rect(src, src_size)),
+                     Some(to_sdl_rect(dest, size)));
+        return;
+    }
+
+    let _ = r.copy_ex(tex,
+                      Some(to_sdl_rect(src, src_size)),
+                      Some(to_sdl_rect(dest, size)),
+                      t.angle, None,
+                      t.flip_h, t.flip_v);
 }
 
 fn render_rect(r: &mut SdlRenderer, camera_offset: Vec2, pos: Point, size: Size, color: Color) {
     let dest = pos + camera_offset;
     r.set_draw_color(color);
-    let _ =  r.fill_rect(Some(to_sdl_rect(dest, size)));
+    // fill_rect hands SDL a pointer to a moved copy of the rect
+    let _ =  r.fill_rects(&[to_sdl_rect(dest, size)]);
     r.set_draw_color(Color::RGB(0, 0, 0));
     let _ =  r.draw_rect(to_sdl_rect(dest, size));
 }
+
+#[cfg(test)]
+mod tests {
+    use scene::Scene;
+    use types::{Point, Size, Depth, RenderInfo, Color};
+    use super::Context;
+
+    struct FillScene {
+        updates: u32,
+    }
+
+    impl Scene for FillScene {
+        fn update(&mut self, _ctx: &mut Context, _dt: f32) {
+            self.updates += 1;
+        }
+
+        fn draw(&self, ctx: &mut Context, _a: f32) {
+            ctx.render(RenderInfo::rect(Point::new(0.0, 0.0), Size::new(8.0, 4.0),
+                                        Depth::new(0, 0.0), Color::RGB(255, 0, 0)));
+            ctx.render(RenderInfo::rect(Point::new(-100.0, 0.0), Size::new(8.0, 4.0),
+                                        Depth::new(0, 0.0), Color::RGB(0, 255, 0)));
+        }
+    }
+
+    #[test]
+    fn headless_render() {
+        let mut ctx = Context::builder().headless().size(16, 8).build().unwrap();
+        let mut scene = FillScene { updates: 0 };
+        ctx.run_scene(&mut scene, Some(3));
+
+        assert_eq!(scene.updates, 3);
+        let stats = ctx.render_stats();
+        assert_eq!((stats.drawn, stats.items_culled, stats.chunks_culled), (1, 1, 0));
+
+        // RGBA8888 is packed, so the bytes of a pixel are reversed in memory
+        let pixels = ctx.read_pixels().unwrap();
+        assert_eq!(pixels.len(), 16 * 8 * 4);
+        let pixel = |x: usize, y: usize| {
+            let i = (y * 16 + x) * 4;
+            u32::from_le_bytes([pixels[i], pixels[i + 1], pixels[i + 2], pixels[i + 3]])
+        };
+        // rects get a black outline
+        assert_eq!(pixel(0, 0), 0x000000ff);
+        assert_eq!(pixel(1, 1), 0xff0000ff);
+        assert_eq!(pixel(6, 2), 0xff0000ff);
+        assert_eq!(pixel(8, 1), 0x000000ff);
+        assert_eq!(pixel(1, 4), 0x000000ff);
+    }
+}
@@ -1,3 +1,4 @@
 pub mod player;
 pub type Player = player::Player;
 pub type PlayerData = player::PlayerData;
+pub type Movement = player::Movement;
@@ -1,9 +1,14 @@
-use types::{Point, Vec2, Direction};
+use types::{Point, Vec2, Size, Rect, Direction};
 use context::Context;
 
 use animation::{AnimManager, AnimData};
 use sprite::{SpriteData, SpriteManager, SpriteCache};
-use state::{StateData, StateManager};
+use state::{StateData, TransitionData, StateParams, StateMachine};
+
+const DEFAULT_HITBOX_SIZE: f32 = 16.0;
+// seconds a key has to be held before the player
+// walks instead of only turning around
+const TURN_TIME: f32 = 0.1;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct PlayerData {
@@ -11,7 +16,42 @@ pub struct PlayerData {
     start_state: String,
     frames: Vec<SpriteData>,
     states: Vec<StateData>,
+    #[serde(default)]
+    transitions: Vec<TransitionData>,
     animations: Vec<AnimData>,
+    // animations from the frame tags of loaded sheets, as <sheet>/<tag>
+    #[serde(default)]
+    tags: Vec<String>,
+    hitbox: Option<HitboxData>,
+    // a slice of a loaded sheet as <sheet>/<slice>, used instead of hitbox
+    hitbox_slice: Option<String>,
+}
+
+// relative to the player position
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HitboxData {
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Movement {
+    // moves continuously in any direction
+    Free,
+    // walks from tile to tile
+    Grid,
+}
+
+impl Movement {
+    pub fn from_name(name: &str) -> Option<Movement> {
+        match name {
+            "free" => Some(Movement::Free),
+            "grid" => Some(Movement::Grid),
+            _ => None
+        }
+    }
 }
 
 pub struct Player {
@@ -19,49 +59,186 @@ pub struct Player {
     vel: Vec2,
     speed: f32,
     facing: Direction,
+    hitbox: Rect,
+    movement: Movement,
+    // position the current grid step ends at
+    target: Option<Point>,
+    // other direction pressed during a step, taken once it ends
+    queued: Option<Direction>,
+    // how long the direction key has been held while standing
+    held: Option<f32>,
     sprites: SpriteManager,
-    states: StateManager,
-    anims: AnimManager,
+    states: StateMachine,
 }
 
 impl Player {
     pub fn new(data: &PlayerData, start_pos: Point, sc: &SpriteCache) -> Player {
-        let sprites = SpriteManager::new(&data.frames, sc);
-        let mut states = StateManager::new(&data.states, &sprites);
-        let anims = AnimManager::new(&data.animations, &sprites);
+        let mut frames = data.frames.clone();
+        let mut animations = data.animations.clone();
+        for tag in &data.tags {
+            match sc.get_anim(tag) {
+                Some(anim) => {
+                    frames.extend(anim.sprite_names().into_iter().map(SpriteData::new));
+                    animations.push(anim.clone());
+                },
+                None => println!("animation tag not found: {}", tag)
+            }
+        }
+
+        let sprites = SpriteManager::new(&frames, sc);
+        let anims = AnimManager::new(&animations, &sprites);
+        let states = StateMachine::new(&data.states, &data.transitions, &data.start_state, &sprites, anims);
 
-        states.set(&data.start_state);
+        let slice = data.hitbox_slice.as_ref().and_then(|name| {
+            let hitbox = sc.get_hitbox(name);
+            if hitbox.is_none() { println!("hitbox slice not found: {}", name); }
+            hitbox
+        });
+
+        let hitbox = match (slice, &data.hitbox) {
+            (Some(slice), _) => slice,
+            (None, Some(h)) => Rect::new(h.x, h.y, h.width, h.height),
+            (None, None) => Rect::new(0.0, 0.0, DEFAULT_HITBOX_SIZE, DEFAULT_HITBOX_SIZE)
+        };
 
         Player {
             pos: start_pos,
             vel: Vec2::new(0.0, 0.0),
             speed: data.speed,
             facing: Direction::Down,
+            hitbox,
+            movement: Movement::Free,
+            target: None,
+            queued: None,
+            held: None,
             sprites: sprites,
-            states: states,
-            anims: anims
+            states
         }
     }
 
     pub fn set_facing(&mut self, dir: Direction) {
         self.facing = dir;
-
-        match self.facing {
-            Direction::Down => self.states.set("idle-down"),
-            Direction::Up => self.states.set("idle-up"),
-            Direction::Left => self.states.set("idle-left"),
-            Direction::Right => self.states.set("idle-right"),
-        }
     }
 
-    pub fn run_anim(&mut self, anim: &str) {
-        self.anims.run(anim);
+    // animation events of the last update, such as footsteps;
+    // nothing in the game reacts to them yet
+    #[allow(dead_code)]
+    pub fn anim_events(&self) -> &[String] {
+        self.states.events()
     }
 
     pub fn next_pos(&self, dt: f32) -> Point {
         self.pos + (self.vel * dt * self.speed)
     }
 
+    pub fn hitbox_at(&self, pos: Point) -> Rect {
+        self.hitbox.offset(pos)
+    }
+
+    // centers the hitbox on p
+    pub fn place_at(&mut self, p: Point) {
+        self.pos = Point::new(p.x - self.hitbox.pos.x - self.hitbox.size.w / 2.0,
+                              p.y - self.hitbox.pos.y - self.hitbox.size.h / 2.0);
+        self.vel = Vec2::new(0.0, 0.0);
+        self.target = None;
+        self.queued = None;
+    }
+
+    // centers the hitbox on the tile it overlaps most
+    pub fn snap_to_grid(&mut self, tilesize: Size) {
+        let center = self.hitbox_at(self.pos).center();
+        let x = (center.x / tilesize.w).floor() + 0.5;
+        let y = (center.y / tilesize.h).floor() + 0.5;
+        self.place_at(Point::new(x * tilesize.w, y * tilesize.h));
+    }
+
+    pub fn movement(&self) -> Movement {
+        self.movement
+    }
+
+    pub fn set_movement(&mut self, movement: Movement) {
+        self.movement = movement;
+        self.target = None;
+        self.queued = None;
+        self.vel = Vec2::new(0.0, 0.0);
+    }
+
+    // tapping a direction only turns, holding it walks a tile per step.
+    // a direction pressed mid-step is taken once the step ends, blocked
+    // tells if the hitbox may not move to a position
+    pub fn update_grid<F>(&mut self, dir: Option<Direction>, tilesize: Size, dt: f32, blocked: F)
+        where F: Fn(&Rect) -> bool {
+        use cgmath::prelude::*;
+
+        let mut dt = dt;
+
+        if let Some(target) = self.target {
+            // holding on keeps walking anyway, only queue a change of direction
+            if dir.is_some() && dir != Some(self.facing) { self.queued = dir; }
+
+            let left = (target - self.pos).magnitude() / self.speed;
+            if dt < left {
+                self.pos = self.pos + (target - self.pos).normalize_to(self.speed * dt);
+                return;
+            }
+
+            // the rest of the frame goes to the next step
+            self.pos = target;
+            self.target = None;
+            self.vel = Vec2::new(0.0, 0.0);
+            dt -= left;
+
+            // a walking player doesn't stop to turn
+            let next = dir.or(self.queued.take());
+            self.held = next.map(|_| TURN_TIME);
+            return self.try_step(next, tilesize, dt, blocked);
+        }
+
+        self.try_step(dir, tilesize, dt, blocked);
+    }
+
+    fn try_step<F>(&mut self, dir: Option<Direction>, tilesize: Size, dt: f32, blocked: F)
+        where F: Fn(&Rect) -> bool {
+        use cgmath::prelude::*;
+
+        let d = match dir {
+            Some(d) => d,
+            None => {
+                self.held = None;
+                return;
+            }
+        };
+
+        match self.held {
+            // walking on, or held long enough after turning
+            Some(t) if t >= TURN_TIME => {},
+            // pressed while already facing that way
+            None if d == self.facing => {},
+            Some(t) if d == self.facing => {
+                self.held = Some(t + dt);
+                if t + dt < TURN_TIME { return; }
+            },
+            _ => {
+                self.set_facing(d);
+                self.held = Some(0.0);
+                return;
+            }
+        }
+
+        self.set_facing(d);
+        self.held = Some(TURN_TIME);
+
+        let unit = d.unit();
+        let target = self.pos + Vec2::new(unit.x * tilesize.w, unit.y * tilesize.h);
+        if blocked(&self.hitbox_at(target)) { return; }
+
+        self.target = Some(target);
+        self.vel = unit;
+
+        let step = (target - self.pos).magnitude().min(self.speed * dt);
+        self.pos += unit * step;
+    }
+
     pub fn get_pos(&self) -> Point {
         self.pos
     }
@@ -75,31 +252,133 @@ impl Player {
     }
 
     pub fn update(&mut self, dt: f32) {
-        self.pos = self.next_pos(dt);
-
-        if self.anims.anim_running() && !self.is_moving() {
-            self.anims.stop_anim();
+        // grid steps are moved by update_grid
+        if self.movement == Movement::Free {
+            self.pos = self.next_pos(dt);
         }
 
-        if self.is_moving() {
-            match self.facing {
-                Direction::Up => self.run_anim("walk-up"),
-                Direction::Down => self.run_anim("walk-down"),
-                Direction::Left => self.run_anim("walk-left"),
-                Direction::Right => self.run_anim("walk-right")
+        use cgmath::prelude::*;
+
+        let params = StateParams {
+            moving: self.is_moving(),
+            facing: self.facing,
+            speed: self.vel.magnitude() * self.speed
+        };
+        self.states.update(&params, dt);
+    }
+
+    pub fn draw(&self, ctx: &mut Context, a: f32, layer: u32) {
+        let index = self.states.current();
+        let dest = self.next_pos(a);
+        self.sprites.draw(index, dest, layer, ctx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use sprite::SpriteCache;
+    use types::{Point, Size, Direction};
+    use super::{Player, PlayerData, TURN_TIME};
+
+    const TILE: Size = Size { w: 16.0, h: 16.0 };
+
+    // 32 pixels per second, half a second per tile
+    fn walker() -> Player {
+        let data: PlayerData = serde_json::from_str(r#"{
+            "speed": 32.0,
+            "start_state": "idle",
+            "frames": [],
+            "states": [{ "name": "idle", "anim": "idle" }],
+            "animations": []
+        }"#).unwrap();
+        Player::new(&data, Point::new(0.0, 0.0), &SpriteCache::new())
+    }
+
+    #[test]
+    fn tags_and_hitbox_slice() {
+        let mut sc = SpriteCache::new();
+        sc.add_sheet("hero", serde_json::from_str(r#"{
+            "frames": [
+                { "filename": "hero 0", "frame": { "x": 0, "y": 0, "w": 16, "h": 24 }, "duration": 100 },
+                { "filename": "hero 1", "frame": { "x": 16, "y": 0, "w": 16, "h": 24 }, "duration": 100 }
+            ],
+            "meta": {
+                "frameTags": [{ "name": "walk", "from": 0, "to": 1 }],
+                "slices": [{ "name": "feet", "keys": [{ "frame": 0, "bounds": { "x": 3, "y": 14, "w": 10, "h": 8 } }] }]
             }
-        }
+        }"#).unwrap()).unwrap();
 
-        self.anims.update(dt);
+        let data: PlayerData = serde_json::from_str(r#"{
+            "speed": 100.0,
+            "start_state": "walk",
+            "frames": [],
+            "states": [{ "name": "walk", "anim": "hero/walk" }],
+            "animations": [],
+            "tags": ["hero/walk", "walk"],
+            "hitbox": { "x": 0, "y": 0, "width": 4, "height": 4 },
+            "hitbox_slice": "hero/feet"
+        }"#).unwrap();
+
+        let mut player = Player::new(&data, Point::new(0.0, 0.0), &sc);
+        let h = player.hitbox;
+        assert_eq!((h.pos.x, h.pos.y, h.size.w, h.size.h), (3.0, 14.0, 10.0, 8.0));
+
+        // the tag's frames are added to the player's
+        assert_eq!(player.sprites.find_index("hero 1"), Some(1));
+        assert_eq!(player.states.current(), 0);
+        player.update(0.1);
+        assert_eq!(player.states.current(), 1);
     }
 
-    pub fn draw(&self, mut ctx: &mut Context, a: f32) {
-        let mut index = self.states.current();
-        if self.anims.anim_running() {
-            index = self.anims.current();
-        }
+    #[test]
+    fn tap_turns_without_moving() {
+        let mut player = walker();
+        player.update_grid(Some(Direction::Right), TILE, TURN_TIME / 2.0, |_| false);
+        player.update_grid(None, TILE, 0.5, |_| false);
 
-        let dest = self.next_pos(a);
-        self.sprites.draw(index, dest, ctx);
+        assert!(player.facing == Direction::Right);
+        assert_eq!(player.pos, Point::new(0.0, 0.0));
+    }
+
+    #[test]
+    fn hold_walks_one_tile() {
+        let mut player = walker();
+        // turns, then walks once held for TURN_TIME
+        player.update_grid(Some(Direction::Right), TILE, 0.05, |_| false);
+        player.update_grid(Some(Direction::Right), TILE, 0.05, |_| false);
+        assert!(player.target.is_none());
+        player.update_grid(Some(Direction::Right), TILE, 0.05, |_| false);
+        assert_eq!(player.target, Some(Point::new(16.0, 0.0)));
+
+        // letting go finishes the step
+        player.update_grid(None, TILE, 0.5, |_| false);
+        assert_eq!(player.pos, Point::new(16.0, 0.0));
+        assert!(player.target.is_none() && !player.is_moving());
+    }
+
+    #[test]
+    fn direction_pressed_mid_step_is_queued() {
+        let mut player = walker();
+        player.update_grid(Some(Direction::Down), TILE, 0.1, |_| false);
+        player.update_grid(Some(Direction::Right), TILE, 0.1, |_| false);
+        assert!(player.facing == Direction::Down);
+
+        player.update_grid(None, TILE, 0.5, |_| false);
+        assert!(player.facing == Direction::Right);
+        assert_eq!(player.target, Some(Point::new(16.0, 16.0)));
+    }
+
+    #[test]
+    fn blocked_step_only_turns() {
+        let mut player = walker();
+        player.update_grid(Some(Direction::Left), TILE, 0.05, |_| true);
+        player.update_grid(Some(Direction::Left), TILE, 0.1, |_| true);
+        player.update_grid(Some(Direction::Left), TILE, 0.1, |_| true);
+
+        assert!(player.facing == Direction::Left);
+        assert_eq!(player.pos, Point::new(0.0, 0.0));
+        assert!(player.target.is_none());
     }
 }
@@ -1,3 +1,7 @@
+// serde_derive 0.9 wraps every derived impl in a named const, which newer
+// compilers flag on each derive in every module. Only upgrading serde fixes it.
+#![allow(non_local_definitions)]
+
 extern crate sdl2;
 extern crate cgmath;
 extern crate time;
@@ -9,6 +13,9 @@ extern crate serde_json;
 
 extern crate base64;
 extern crate byteorder;
+extern crate xml;
+extern crate flate2;
+extern crate zstd;
 
 mod types;
 mod context;
@@ -25,7 +32,52 @@ mod tilemap;
 mod util;
 
 fn main() {
-    let mut main_ctx = context::Context::new();
-    let mut game_scene = scene::GameScene::new(&mut main_ctx);
-    main_ctx.run_scene(&mut game_scene);
+    // --windowed opens a regular window instead of fullscreen,
+    // --headless <frames> renders offscreen for a fixed number of frames,
+    // --map <name> starts on a map from assets/maps.json
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut builder = context::Context::builder();
+    let mut frames = None;
+    let mut map = None;
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--windowed" => builder = builder.windowed().size(1280, 720),
+            "--headless" => {
+                builder = builder.headless().size(640, 480);
+                // the frame count is optional
+                frames = match args.get(i + 1).and_then(|n| n.parse().ok()) {
+                    Some(n) => { i += 1; Some(n) },
+                    None => Some(1)
+                };
+            },
+            "--map" => {
+                map = args.get(i + 1).map(|m| m.as_str());
+                i += 1;
+            },
+            a => println!("unknown argument: {}", a)
+        }
+        i += 1;
+    }
+
+    let mut main_ctx = builder.build().unwrap();
+    let mut game_scene = match scene::GameScene::new(&mut main_ctx, map) {
+        Ok(scene) => scene,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
+    main_ctx.run_scene(&mut game_scene, frames);
+
+    if main_ctx.is_headless() {
+        let pixels = main_ctx.read_pixels().unwrap();
+        let size = main_ctx.screen_size();
+        let stats = main_ctx.render_stats();
+        println!("rendered {} frames at {}x{} ({} bytes)",
+                 frames.unwrap_or(0), size.w, size.h, pixels.len());
+        println!("last frame: {} drawn, {} items and {} chunks culled",
+                 stats.drawn, stats.items_culled, stats.chunks_culled);
+    }
 }
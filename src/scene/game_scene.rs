@@ -1,17 +1,33 @@
 use scene::Scene;
-use tilemap::Tilemap;
-use entity::{Player, PlayerData};
-use types::{KeyAction, Point, Vec2, Direction};
+use tilemap::{Tilemap, MapRegistry, Warp};
+use entity::{Player, PlayerData, Movement};
+use types::{KeyAction, Point, Vec2, Direction, Depth, Color, RenderInfo};
 use context::Context;
 
+// seconds for each half of a map transition
+const FADE_TIME: f32 = 0.3;
+
+enum Transition {
+    FadeOut(Warp, f32),
+    FadeIn(f32),
+}
+
 pub struct GameScene {
+    maps: MapRegistry,
+    map_name: String,
     map: Tilemap,
     player: Player,
+    transition: Option<Transition>,
+    // warps only trigger when the player steps onto them
+    in_warp: bool,
 }
 
 impl GameScene {
-    pub fn new(mut ctx: &mut Context) -> GameScene {
-        let map = Tilemap::new(ctx);
+    // None starts on the registry's start map
+    pub fn new(ctx: &mut Context, map_name: Option<&str>) -> Result<GameScene, String> {
+        let maps = MapRegistry::load("maps.json")?;
+        let map_name = String::from(map_name.unwrap_or(maps.start()));
+        let map = maps.load_map(ctx, &map_name)?;
 
         ctx.load_sheet("test");
         ctx.load_sheet("female0");
@@ -20,24 +36,96 @@ impl GameScene {
         let player_data: PlayerData = ::util::load_data("player-female0.json").unwrap();
         let player = Player::new(&player_data, start_pos, ctx.get_sprite_cache());
 
-        GameScene {
+        let mut scene = GameScene {
+            maps,
+            map_name,
             map: map,
             player: player,
+            transition: None,
+            in_warp: false,
+        };
+        scene.apply_movement();
+        Ok(scene)
+    }
+
+    // free or grid from the map's movement property, free if it is missing
+    fn apply_movement(&mut self) {
+        let movement = match self.map.properties().get_str("movement") {
+            Some(name) => Movement::from_name(name).unwrap_or_else(|| {
+                println!("unknown movement {}, using free movement", name);
+                Movement::Free
+            }),
+            None => Movement::Free
+        };
+
+        self.player.set_movement(movement);
+        if movement == Movement::Grid {
+            self.player.snap_to_grid(self.map.tile_size());
         }
     }
 
-    pub fn try_move_player(&mut self, v: Vec2) {
-        // finding next player position for collision
-        // let next_pos = self.player.next_pos(dt, v);
-        // let new_x = self.player.next_pos(dt, Point::new(v.x(), 0.0));
-        // let new_y = self.player.next_pos(dt, Point::new(0.0, v.y()));
+    // the old map is released first and loaded again if the new one fails.
+    // if that fails too both errors are returned and the scene is left on
+    // the empty unloaded map, where nothing is drawn or walkable
+    pub fn switch_map(&mut self, ctx: &mut Context, name: &str) -> Result<(), String> {
+        if self.maps.path(name).is_none() {
+            return Err(format!("unknown map: {}", name));
+        }
 
-        self.player.set_vel(v);
+        self.map.unload();
+        match self.maps.load_map(ctx, name) {
+            Ok(map) => {
+                self.map = map;
+                self.map_name = String::from(name);
+                Ok(())
+            },
+            Err(err) => match self.maps.load_map(ctx, &self.map_name) {
+                Ok(map) => {
+                    self.map = map;
+                    Err(err)
+                },
+                Err(reload_err) => Err(format!("{}, reloading {} failed too: {}",
+                                               err, self.map_name, reload_err))
+            }
+        }
     }
-}
 
-impl Scene for GameScene {
-    fn update(&mut self, ctx: &mut Context, dt: f32) {
+    pub fn try_move_player(&mut self, v: Vec2, dt: f32) {
+        // resolve each axis separately so the player
+        // slides along walls instead of sticking to them
+        let mut vel = Vec2::new(v.x, 0.0);
+        self.player.set_vel(vel);
+        if self.is_blocked(dt) { vel.x = 0.0; }
+
+        vel.y = v.y;
+        self.player.set_vel(vel);
+        if self.is_blocked(dt) { vel.y = 0.0; }
+
+        self.player.set_vel(vel);
+    }
+
+    fn is_blocked(&self, dt: f32) -> bool {
+        let next = self.player.hitbox_at(self.player.next_pos(dt));
+        self.map.collision().is_blocked(&next)
+    }
+
+    fn move_on_grid(&mut self, dir: Option<Direction>, dt: f32) {
+        let map = &self.map;
+        self.player.update_grid(dir, map.tile_size(), dt, |r| map.collision().is_blocked(r));
+    }
+
+    fn handle_input(&mut self, ctx: &Context, dt: f32) {
+        if self.player.movement() == Movement::Grid {
+            let dir = match ctx.last_key() {
+                Some(&KeyAction::Down) => Some(Direction::Down),
+                Some(&KeyAction::Up) => Some(Direction::Up),
+                Some(&KeyAction::Left) => Some(Direction::Left),
+                Some(&KeyAction::Right) => Some(Direction::Right),
+                None => None
+            };
+            return self.move_on_grid(dir, dt);
+        }
+
         let mut move_intention = Vec2::new(0.0, 0.0);
 
         for key in ctx.held_keys() {
@@ -54,7 +142,7 @@ impl Scene for GameScene {
             move_intention = move_intention.normalize_to(1.0)
         }
 
-        self.try_move_player(move_intention);
+        self.try_move_player(move_intention, dt);
 
         match ctx.last_key() {
             Some(&KeyAction::Down) => self.player.set_facing(Direction::Down),
@@ -63,14 +151,152 @@ impl Scene for GameScene {
             Some(&KeyAction::Right) => self.player.set_facing(Direction::Right),
             _ => {}
         }
+    }
+
+    fn check_warps(&mut self) {
+        let hitbox = self.player.hitbox_at(self.player.get_pos());
+        let warp = self.map.warp_at(&hitbox).cloned();
+        let entered = !self.in_warp;
+        self.in_warp = warp.is_some();
+
+        if let (true, Some(warp)) = (entered, warp) {
+            self.transition = Some(Transition::FadeOut(warp, 0.0));
+        }
+    }
+
+    fn update_transition(&mut self, ctx: &mut Context, dt: f32) {
+        self.transition = match self.transition.take() {
+            Some(Transition::FadeOut(warp, t)) => {
+                if t + dt < FADE_TIME {
+                    Some(Transition::FadeOut(warp, t + dt))
+                } else {
+                    // the screen is black, swap the map
+                    self.warp(ctx, &warp);
+                    Some(Transition::FadeIn(0.0))
+                }
+            },
+            Some(Transition::FadeIn(t)) if t + dt < FADE_TIME => Some(Transition::FadeIn(t + dt)),
+            _ => None
+        };
+    }
+
+    fn warp(&mut self, ctx: &mut Context, warp: &Warp) {
+        if let Err(err) = self.switch_map(ctx, &warp.map) {
+            println!("could not warp to {}: {}", warp.map, err);
+            return;
+        }
+
+        match self.map.spawn_point(&warp.spawn) {
+            Some(p) => self.player.place_at(p),
+            None => println!("map {} has no spawn {}", warp.map, warp.spawn)
+        }
+        self.apply_movement();
+
+        if let Some(dir) = warp.facing {
+            self.player.set_facing(dir);
+        }
+
+        // arriving on a warp must not send the player straight back
+        let hitbox = self.player.hitbox_at(self.player.get_pos());
+        self.in_warp = self.map.warp_at(&hitbox).is_some();
+        ctx.snap_camera(self.player.get_pos());
+    }
+
+    // opacity of the black overlay, 0 without a transition
+    fn fade(&self) -> f32 {
+        match self.transition {
+            Some(Transition::FadeOut(_, t)) => t / FADE_TIME,
+            Some(Transition::FadeIn(t)) => 1.0 - t / FADE_TIME,
+            None => 0.0
+        }
+    }
+}
+
+impl Scene for GameScene {
+    fn update(&mut self, ctx: &mut Context, dt: f32) {
+        // the player stands still while the screen fades
+        if self.transition.is_some() {
+            self.player.set_vel(Vec2::new(0.0, 0.0));
+            self.update_transition(ctx, dt);
+        } else {
+            self.handle_input(ctx, dt);
+        }
 
         let player_pos = self.player.get_pos();
         ctx.set_camera_target(player_pos);
         self.player.update(dt);
+        self.map.update(dt);
+
+        if self.transition.is_none() {
+            self.check_warps();
+        }
     }
 
-    fn draw(&self, mut ctx: &mut Context, a: f32) {
-        self.player.draw(ctx, a);
+    fn draw(&self, ctx: &mut Context, a: f32) {
+        self.player.draw(ctx, a, self.map.entity_layer());
         self.map.draw(ctx);
+
+        let fade = self.fade();
+        if fade > 0.0 {
+            // screen space overlay on top of everything
+            let mut overlay = RenderInfo::rect(Point::new(0.0, 0.0), ctx.screen_size(),
+                                               Depth::new(u32::MAX, 0.0),
+                                               Color::RGB(0, 0, 0));
+            overlay.alpha = (fade.min(1.0) * 255.0) as u8;
+            overlay.parallax = Vec2::new(0.0, 0.0);
+            ctx.render(overlay);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use context::Context;
+    use super::GameScene;
+
+    #[test]
+    fn headless_test_map() {
+        let mut ctx = Context::builder().headless().size(64, 48).build().unwrap();
+        let mut scene = GameScene::new(&mut ctx, Some("test")).unwrap();
+        ctx.run_scene(&mut scene, Some(30));
+
+        // the tiles of two layers all lie outside the view, those of
+        // the entity layer are drawn and culled one by one
+        let stats = ctx.render_stats();
+        assert_eq!((stats.drawn, stats.items_culled, stats.chunks_culled), (15, 120, 2));
+
+        let pixels = ctx.read_pixels().unwrap();
+        let pixel = |x: usize, y: usize| {
+            let i = (y * 64 + x) * 4;
+            u32::from_le_bytes([pixels[i], pixels[i + 1], pixels[i + 2], pixels[i + 3]])
+        };
+        // left of the map is the clear color, right of it the ground
+        assert_eq!(pixel(0, 0), 0x000000ff);
+        assert_eq!(pixel(7, 47), 0x000000ff);
+        assert_eq!(pixel(8, 0), 0xd1a569ff);
+        assert!(pixel(63, 47) >> 8 != 0);
+    }
+
+    #[test]
+    fn failed_switch() {
+        let mut ctx = Context::builder().headless().size(64, 48).build().unwrap();
+        let mut scene = GameScene::new(&mut ctx, Some("test")).unwrap();
+        scene.maps = serde_json::from_str(r#"{
+            "start": "test",
+            "maps": { "test": "testmap.json", "broken": "missing.json" }
+        }"#).unwrap();
+
+        // the old map is loaded again
+        assert!(scene.switch_map(&mut ctx, "broken").is_err());
+        assert_eq!(scene.map_name, "test");
+        assert!(!scene.map.collision().is_solid(29, 29));
+
+        // if that fails too both errors are kept and nothing is walkable
+        scene.map_name = String::from("gone");
+        let err = scene.switch_map(&mut ctx, "broken").err().unwrap();
+        assert!(err.contains("missing.json") && err.contains("unknown map: gone"), "{}", err);
+        assert!(scene.map.collision().is_solid(29, 29));
     }
 }
@@ -0,0 +1,293 @@
+use serde_json::{self, Value};
+
+use types::{Point, Vec2, Size, Rect};
+use animation::AnimData;
+
+use super::sheet::{Sheet, SheetFrame};
+
+#[derive(Serialize, Deserialize, Debug)]
+struct FrameData {
+    // only set in the array variant
+    filename: Option<String>,
+    frame: RectData,
+    #[serde(default)]
+    rotated: bool,
+    // trimmed area within the untrimmed frame
+    #[serde(rename = "spriteSourceSize")]
+    sprite_source_size: Option<RectData>,
+    #[serde(rename = "sourceSize")]
+    source_size: Option<SizeData>,
+    // milliseconds
+    duration: Option<f32>
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct MetaData {
+    image: Option<String>,
+    #[serde(rename = "frameTags", default)]
+    frame_tags: Vec<TagData>,
+    #[serde(default)]
+    slices: Vec<SliceData>
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TagData {
+    name: String,
+    from: usize,
+    to: usize,
+    // forward, reverse, pingpong or pingpong_reverse
+    direction: Option<String>,
+    // play count, infinite if missing
+    repeat: Option<String>
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SliceData {
+    name: String,
+    keys: Vec<SliceKeyData>
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SliceKeyData {
+    frame: usize,
+    bounds: RectData
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SizeData {
+    w: f32,
+    h: f32
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RectData {
+    x: f32,
+    y: f32,
+    w: f32,
+    h: f32
+}
+
+// seconds, for frames without a duration
+const DEFAULT_DURATION: f32 = 0.1;
+
+// aseprite's json export, hash or array. tags become animations,
+// slices hitboxes with the bounds of their first key. texturepacker's
+// json formats are the same apart from the tags
+pub fn parse(mut value: Value) -> Result<Sheet, String> {
+    let meta: MetaData = match value.get("meta") {
+        Some(meta) => from_value(meta.clone(), "meta")?,
+        None => return Err(String::from("aseprite sheet without meta"))
+    };
+
+    let frames = match value.as_object_mut().and_then(|m| m.remove("frames")) {
+        Some(Value::Array(list)) => {
+            let mut frames = Vec::new();
+            for v in list {
+                let data: FrameData = from_value(v, "frame")?;
+                let name = match data.filename {
+                    Some(ref name) => name.clone(),
+                    None => return Err(String::from("aseprite frame without filename"))
+                };
+                frames.push((name, data));
+            }
+            frames
+        },
+        Some(Value::Object(map)) => {
+            // the map loses the file's order, aseprite numbers the frames
+            let single = map.len() == 1;
+            let mut frames = Vec::new();
+            for (name, v) in map {
+                let number = match frame_number(&name) {
+                    Some(number) => number,
+                    None if single => 0,
+                    None => return Err(format!("aseprite frame {} has no number to order it by", name))
+                };
+                frames.push((number, name, from_value(v, "frame")?));
+            }
+
+            frames.sort_by_key(|f| f.0);
+            frames.into_iter().map(|(_, name, data)| (name, data)).collect()
+        },
+        _ => return Err(String::from("aseprite frames must be a list or a map"))
+    };
+
+    let mut anims = Vec::new();
+    for tag in &meta.frame_tags {
+        if tag.from > tag.to || tag.to >= frames.len() {
+            println!("frame tag {} out of range", tag.name);
+            continue;
+        }
+
+        let direction = tag.direction.as_ref().map_or("forward", |d| d.as_str());
+        let pingpong = direction == "pingpong" || direction == "pingpong_reverse";
+        let once = tag.repeat.as_deref() == Some("1");
+
+        let mut range: Vec<usize> = (tag.from..tag.to + 1).collect();
+        if direction == "reverse" || direction == "pingpong_reverse" {
+            range.reverse();
+        }
+
+        let mode = match (pingpong, once) {
+            (true, true) => {
+                // one pass there and back, ending on the first frame
+                let back: Vec<usize> = range[..range.len() - 1].iter().rev().cloned().collect();
+                range.extend(back);
+                "once"
+            },
+            (false, true) => "once",
+            (true, false) => "ping-pong",
+            (false, false) => "loop"
+        };
+
+        let mut anim = AnimData::new(&tag.name, mode);
+
+        for i in range {
+            let (ref name, ref data) = frames[i];
+            anim.push_frame(name, data.duration.map_or(DEFAULT_DURATION, |d| d / 1000.0));
+        }
+        anims.push(anim);
+    }
+
+    let hitboxes = meta.slices.iter().filter_map(|s| {
+        s.keys.first().map(|k| {
+            let b = &k.bounds;
+            (s.name.clone(), Rect::new(b.x, b.y, b.w, b.h))
+        })
+    }).collect();
+
+    let frames = frames.into_iter().map(|(name, data)| {
+        let size = Size::new(data.frame.w, data.frame.h);
+        let offset = data.sprite_source_size.as_ref().map_or(Vec2::new(0.0, 0.0), |s| Vec2::new(s.x, s.y));
+
+        SheetFrame {
+            name,
+            pos: Point::new(data.frame.x, data.frame.y),
+            size,
+            offset,
+            source_size: data.source_size.as_ref().map_or(size, |s| Size::new(s.w, s.h)),
+            rotated: data.rotated
+        }
+    }).collect();
+
+    Ok(Sheet {
+        image: meta.image,
+        frames,
+        anims,
+        hitboxes
+    })
+}
+
+fn from_value<T>(value: Value, what: &str) -> Result<T, String>
+    where T: ::serde::Deserialize {
+    serde_json::from_value(value).map_err(|err| format!("invalid aseprite {}: {}", what, err))
+}
+
+// the last number, as in aseprite's default {title} {frame}.{extension}
+fn frame_number(name: &str) -> Option<u32> {
+    let end = match name.rfind(|c: char| c.is_ascii_digit()) {
+        Some(i) => i + 1,
+        None => return None
+    };
+
+    let start = name[..end].rfind(|c: char| !c.is_ascii_digit()).map_or(0, |i| i + 1);
+    name[start..end].parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+    use super::parse;
+
+    #[test]
+    fn hash_with_tags_and_slices() {
+        let value = serde_json::from_str(r##"{
+            "frames": {
+                "hero 0.aseprite": { "frame": { "x": 0, "y": 0, "w": 16, "h": 24 }, "duration": 100 },
+                "hero 1.aseprite": { "frame": { "x": 16, "y": 0, "w": 16, "h": 24 }, "duration": 150 },
+                "hero 2.aseprite": { "frame": { "x": 32, "y": 0, "w": 16, "h": 24 }, "duration": 100 },
+                "hero 10.aseprite": { "frame": { "x": 0, "y": 24, "w": 16, "h": 24 }, "duration": 100 }
+            },
+            "meta": {
+                "image": "hero.png",
+                "frameTags": [
+                    { "name": "walk", "from": 1, "to": 2, "direction": "reverse" },
+                    { "name": "wave", "from": 0, "to": 2, "direction": "pingpong" },
+                    { "name": "attack", "from": 2, "to": 3, "direction": "forward", "repeat": "1" },
+                    { "name": "nod", "from": 0, "to": 2, "direction": "pingpong_reverse", "repeat": "1" }
+                ],
+                "slices": [
+                    { "name": "hitbox", "color": "#0000ffff",
+                      "keys": [{ "frame": 0, "bounds": { "x": 3, "y": 14, "w": 10, "h": 8 } }] }
+                ]
+            }
+        }"##).unwrap();
+
+        let sheet = parse(value).unwrap();
+        let names: Vec<&str> = sheet.frames.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["hero 0.aseprite", "hero 1.aseprite", "hero 2.aseprite", "hero 10.aseprite"]);
+        assert_eq!(sheet.image, Some(String::from("hero.png")));
+
+        assert_eq!(sheet.anims[0].sprite_names(), vec!["hero 2.aseprite", "hero 1.aseprite"]);
+        assert_eq!(sheet.anims[2].sprite_names(), vec!["hero 2.aseprite", "hero 10.aseprite"]);
+        assert_eq!(sheet.anims[3].sprite_names(), vec!["hero 2.aseprite", "hero 1.aseprite", "hero 0.aseprite",
+                                                       "hero 1.aseprite", "hero 2.aseprite"]);
+
+        let (ref name, ref hitbox) = sheet.hitboxes[0];
+        assert_eq!(name, "hitbox");
+        assert_eq!((hitbox.pos.x, hitbox.pos.y, hitbox.size.w, hitbox.size.h), (3.0, 14.0, 10.0, 8.0));
+    }
+
+    #[test]
+    fn hash_needs_frame_numbers() {
+        let value = serde_json::from_str(r#"{
+            "frames": {
+                "idle.png": { "frame": { "x": 0, "y": 0, "w": 16, "h": 24 } },
+                "hero 1.png": { "frame": { "x": 16, "y": 0, "w": 16, "h": 24 } }
+            },
+            "meta": {}
+        }"#).unwrap();
+        assert!(parse(value).is_err());
+
+        let value = serde_json::from_str(r#"{
+            "frames": { "idle.png": { "frame": { "x": 0, "y": 0, "w": 16, "h": 24 } } },
+            "meta": {}
+        }"#).unwrap();
+        assert_eq!(parse(value).unwrap().frames[0].name, "idle.png");
+    }
+
+    #[test]
+    fn trimmed_and_rotated() {
+        let value = serde_json::from_str(r#"{
+            "frames": [{
+                "filename": "tree", "frame": { "x": 10, "y": 20, "w": 30, "h": 50 },
+                "rotated": true, "trimmed": true,
+                "spriteSourceSize": { "x": 4, "y": 6, "w": 30, "h": 50 },
+                "sourceSize": { "w": 40, "h": 64 }
+            }],
+            "meta": { "image": "atlas.png", "size": { "w": 256, "h": 256 } }
+        }"#).unwrap();
+
+        let frame = &parse(value).unwrap().frames[0];
+        assert!(frame.rotated);
+        assert_eq!((frame.size.w, frame.size.h), (30.0, 50.0));
+        assert_eq!((frame.offset.x, frame.offset.y), (4.0, 6.0));
+        assert_eq!((frame.source_size.w, frame.source_size.h), (40.0, 64.0));
+    }
+
+    #[test]
+    fn array_keeps_order() {
+        let value = serde_json::from_str(r#"{
+            "frames": [
+                { "filename": "b", "frame": { "x": 8, "y": 0, "w": 8, "h": 8 }, "duration": 50 },
+                { "filename": "a", "frame": { "x": 0, "y": 0, "w": 8, "h": 8 }, "duration": 50 }
+            ],
+            "meta": { "frameTags": [{ "name": "blink", "from": 0, "to": 1 }] }
+        }"#).unwrap();
+
+        let sheet = parse(value).unwrap();
+        assert_eq!(sheet.frames[0].name, "b");
+        assert_eq!(sheet.frames[1].pos.x, 0.0);
+        assert_eq!(sheet.anims[0].sprite_names(), vec!["b", "a"]);
+        assert!(sheet.image.is_none() && sheet.hitboxes.is_empty());
+    }
+}
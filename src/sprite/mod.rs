@@ -4,42 +4,114 @@ pub type SpriteCache = sprite_cache::SpriteCache;
 mod sprite_manager;
 pub type SpriteManager = sprite_manager::SpriteManager;
 
+mod sheet;
+mod aseprite;
+
 use std::cell::RefCell;
 use std::rc::Rc;
 
-use types::{Point, Size, RenderInfo, Texture};
-use context::Context;
+use types::{Point, Vec2, Size, Depth, Transform, RenderInfo, Texture};
+
+use self::sheet::SheetFrame;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SpriteData {
     name: String
 }
 
-#[derive(Clone)]
+impl SpriteData {
+    pub fn new(name: &str) -> SpriteData {
+        SpriteData { name: String::from(name) }
+    }
+}
+
+// a sprite of the SpriteCache. resolve names to handles once when
+// loading, lookups by handle are indexing
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct SpriteHandle(u32);
+
 pub struct Sprite {
-    pub name: String,
+    // trimmed size, as drawn
     size: Size,
     src: Point,
-    src_size: Size,
-    scale: f32,
-    tex: Rc<RefCell<Texture>>
+    // trimmed image within the untrimmed frame
+    offset: Vec2,
+    source_size: Size,
+    // stored turned clockwise in the atlas
+    rotated: bool,
+    // index of the sheet in the SpriteCache
+    sheet: usize
 }
 
 impl Sprite {
-    pub fn new(name: &str, size: Size, src: Point, src_size: Size, tex: Rc<RefCell<Texture>>) -> Sprite {
+    pub fn new(frame: &SheetFrame, sheet: usize) -> Sprite {
         Sprite {
-            name: String::from(name),
-            size: size,
-            src: src,
-            src_size: src_size,
-            scale: 1.0,
-            tex: tex
+            size: frame.size,
+            src: frame.pos,
+            offset: frame.offset,
+            source_size: frame.source_size,
+            rotated: frame.rotated,
+            sheet
+        }
+    }
+
+    // with the untrimmed frame's top left corner at pos
+    pub fn render_info(&self, pos: Point, layer: u32, tex: Rc<RefCell<Texture>>) -> RenderInfo {
+        // sort by the untrimmed frame so trimming doesn't change the order
+        let depth = Depth::new(layer, pos.y + self.source_size.h);
+        let (dest, stored, transform) = self.placement(pos);
+        RenderInfo::texture(dest, stored,
+                            self.src, stored,
+                            depth, tex).transformed(transform)
+    }
+
+    // where the stored area goes and the turn that restores it,
+    // for the untrimmed frame at pos
+    fn placement(&self, pos: Point) -> (Point, Size, Transform) {
+        let dest = pos + self.offset;
+        if !self.rotated {
+            return (dest, self.size, Transform::identity());
         }
+
+        // draw the area as stored and turn it back around the sprite's center
+        let stored = Size::new(self.size.h, self.size.w);
+        let dest = dest + Vec2::new((self.size.w - stored.w) / 2.0, (self.size.h - stored.h) / 2.0);
+        (dest, stored, Transform { angle: -90.0, flip_h: false, flip_v: false })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use types::{Point, Vec2, Size};
+    use super::Sprite;
+    use super::sheet::SheetFrame;
+
+    fn sprite(rotated: bool) -> Sprite {
+        let frame = SheetFrame {
+            name: String::from("tree"),
+            pos: Point::new(10.0, 20.0),
+            size: Size::new(30.0, 50.0),
+            offset: Vec2::new(4.0, 6.0),
+            source_size: Size::new(40.0, 64.0),
+            rotated
+        };
+        Sprite::new(&frame, 0)
+    }
+
+    #[test]
+    fn trimmed_placement() {
+        let (dest, size, transform) = sprite(false).placement(Point::new(100.0, 200.0));
+        assert_eq!((dest.x, dest.y), (104.0, 206.0));
+        assert_eq!((size.w, size.h), (30.0, 50.0));
+        assert!(transform.is_identity());
     }
 
-    pub fn draw(&self, pos: Point, ctx: &mut Context) {
-        ctx.render(RenderInfo::texture(pos, self.size,
-                                       self.src, self.src_size,
-                                       pos.y, self.tex.clone()));
+    #[test]
+    fn rotated_placement() {
+        // stored 50x30, centered on the upright 30x50 area
+        let (dest, size, transform) = sprite(true).placement(Point::new(100.0, 200.0));
+        assert_eq!((dest.x, dest.y), (94.0, 216.0));
+        assert_eq!((size.w, size.h), (50.0, 30.0));
+        assert_eq!(transform.angle, -90.0);
     }
 }
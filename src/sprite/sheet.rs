@@ -0,0 +1,83 @@
+use serde_json::{self, Value};
+
+use types::{Point, Vec2, Size, Rect};
+use animation::AnimData;
+
+use super::aseprite;
+
+// trimmed frames store only the opaque part,
+// rotated ones are stored turned 90 degrees clockwise
+pub struct SheetFrame {
+    pub name: String,
+    // top left corner in the sheet's image
+    pub pos: Point,
+    // trimmed size, before rotation
+    pub size: Size,
+    // top left corner of the trimmed image within the untrimmed frame
+    pub offset: Vec2,
+    // untrimmed size
+    pub source_size: Size,
+    pub rotated: bool,
+}
+
+pub struct Sheet {
+    // image file next to the metadata, None for <name>.png
+    pub image: Option<String>,
+    pub frames: Vec<SheetFrame>,
+    pub anims: Vec<AnimData>,
+    // named boxes relative to a frame's top left corner
+    pub hitboxes: Vec<(String, Rect)>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SpritesheetData {
+    name: String,
+    size: SizeData,
+    o_size: SizeData,
+    pos: PointData,
+    offset: PointData,
+    #[serde(default)]
+    rotated: bool
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PointData {
+    x: f32,
+    y: f32
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SizeData {
+    width: f32,
+    height: f32
+}
+
+// our own frame list or an aseprite or texturepacker export. in ours
+// size is the trimmed size, o_size the untrimmed one and offset where
+// the trimmed image starts in it
+pub fn parse_sheet(value: Value) -> Result<Sheet, String> {
+    if value.get("frames").is_some() {
+        return aseprite::parse(value);
+    }
+
+    let data: Vec<SpritesheetData> = match serde_json::from_value(value) {
+        Ok(data) => data,
+        Err(err) => return Err(format!("invalid sprite sheet: {}", err))
+    };
+
+    let frames = data.iter().map(|sd| SheetFrame {
+        name: sd.name.clone(),
+        pos: Point::new(sd.pos.x, sd.pos.y),
+        size: Size::new(sd.size.width, sd.size.height),
+        offset: Vec2::new(sd.offset.x, sd.offset.y),
+        source_size: Size::new(sd.o_size.width, sd.o_size.height),
+        rotated: sd.rotated
+    }).collect();
+
+    Ok(Sheet {
+        image: None,
+        frames,
+        anims: Vec::new(),
+        hitboxes: Vec::new()
+    })
+}
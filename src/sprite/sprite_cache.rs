@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::fs::File;
 use std::path::Path;
 use std::io::prelude::*;
@@ -7,33 +8,22 @@ use std::rc::Rc;
 use sdl2::render::Renderer;
 use sdl2::image::LoadTexture;
 
-use types::{Size, Point};
+use serde_json::Value;
 
-use super::Sprite;
+use types::{Point, Rect, RenderInfo, Texture};
+use animation::AnimData;
+
+use super::{Sprite, SpriteHandle};
+use super::sheet::parse_sheet;
 
 pub struct SpriteCache {
     sprites: Vec<Sprite>,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-struct SpritesheetData {
-    name: String,
-    size: SizeData,
-    o_size: SizeData,
-    pos: PointData,
-    offset: PointData
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-struct PointData {
-    x: f32,
-    y: f32
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-struct SizeData {
-    width: f32,
-    height: f32
+    handles: HashMap<String, SpriteHandle>,
+    anims: HashMap<String, AnimData>,
+    hitboxes: HashMap<String, Rect>,
+    // names and images of the sheets, a sprite refers to its sheet by index
+    sheets: Vec<String>,
+    textures: Vec<Option<Rc<RefCell<Texture>>>>,
 }
 
 impl SpriteCache {
@@ -42,46 +32,148 @@ impl SpriteCache {
 
         SpriteCache {
             sprites: sprites,
+            handles: HashMap::new(),
+            anims: HashMap::new(),
+            hitboxes: HashMap::new(),
+            sheets: Vec::new(),
+            textures: Vec::new()
         }
     }
 
+    // loads assets/<name>.json, our own frame list or an aseprite
+    // export, with its image
     pub fn load_sheet(&mut self, name: &str, r: &mut Renderer) {
         let mut path = String::from("assets/");
         path.push_str(name);
 
         let mut path_meta = String::from(path.clone());
-        let mut path_img = String::from(path.clone());
-
         path_meta.push_str(".json");
-        path_img.push_str(".png");
 
         // load metadata
         let mut file = File::open(Path::new(&path_meta)).unwrap();
         let mut content = String::new();
         let _ = file.read_to_string(&mut content);
 
-        let data: Vec<SpritesheetData> = super::super::serde_json::from_str(&content).unwrap();
+        let value = super::super::serde_json::from_str(&content).unwrap();
+        let image = match self.add_sheet(name, value) {
+            Ok(image) => image,
+            Err(err) => {
+                println!("could not load sprite sheet {}: {}", name, err);
+                return;
+            }
+        };
+
+        let path_img = match image {
+            Some(ref image) => format!("assets/{}", image),
+            None => format!("{}.png", path)
+        };
 
         let tex = r.load_texture(Path::new(&path_img)).unwrap();
-        let tex = Rc::new(RefCell::new(tex));
-
-        for sd in &data {
-            let sprite = Sprite::new(&sd.name,
-                                     Size::new(sd.size.width, sd.size.height),
-                                     Point::new(sd.pos.x, sd.pos.y),
-                                     Size::new(sd.size.width, sd.size.height),
-                                     tex.clone());
-            self.sprites.push(sprite);
+        let sheet = self.sheet_index(name);
+        self.textures[sheet] = Some(Rc::new(RefCell::new(tex)));
+    }
+
+    // adds sprites, tag animations and slice hitboxes. returns the
+    // image the sheet names, load_sheet sets the texture
+    pub fn add_sheet(&mut self, name: &str, value: Value) -> Result<Option<String>, String> {
+        let sheet = parse_sheet(value)?;
+        let index = self.sheet_index(name);
+
+        for frame in &sheet.frames {
+            let sprite = Sprite::new(frame, index);
+
+            // reloading a sheet keeps the handles of its sprites,
+            // a name already taken by another sheet keeps the first sprite
+            match self.handles.get(&frame.name).map(|h| h.0 as usize) {
+                Some(i) if self.sprites[i].sheet == index => self.sprites[i] = sprite,
+                Some(i) => println!("sprite {} of sheet {} is already loaded from sheet {}",
+                                    frame.name, name, self.sheets[self.sprites[i].sheet]),
+                None => {
+                    let handle = SpriteHandle(self.sprites.len() as u32);
+                    self.sprites.push(sprite);
+                    self.handles.insert(frame.name.clone(), handle);
+                }
+            }
+        }
+
+        // tags and slices of different sheets often share names
+        for mut anim in sheet.anims {
+            let key = format!("{}/{}", name, anim.name());
+            anim.set_name(&key);
+            self.anims.insert(key, anim);
+        }
+
+        for (slice, hitbox) in sheet.hitboxes {
+            self.hitboxes.insert(format!("{}/{}", name, slice), hitbox);
         }
+
+        Ok(sheet.image)
     }
 
-    pub fn get_sprite(&self, name: &str) -> Option<Sprite> {
-        for sprite in self.sprites.iter() {
-            if sprite.name == name {
-                return Some(sprite.clone());
+    fn sheet_index(&mut self, name: &str) -> usize {
+        match self.sheets.iter().position(|s| s == name) {
+            Some(i) => i,
+            None => {
+                self.sheets.push(String::from(name));
+                self.textures.push(None);
+                self.sheets.len() - 1
             }
         }
+    }
+
+    pub fn find(&self, name: &str) -> Option<SpriteHandle> {
+        self.handles.get(name).cloned()
+    }
+
+    pub fn get(&self, handle: SpriteHandle) -> &Sprite {
+        &self.sprites[handle.0 as usize]
+    }
+
+    // None while the sheet has no image
+    pub fn render_info(&self, handle: SpriteHandle, pos: Point, layer: u32) -> Option<RenderInfo> {
+        let sprite = self.get(handle);
+        self.textures[sprite.sheet].as_ref().map(|tex| sprite.render_info(pos, layer, tex.clone()))
+    }
+
+    // named <sheet>/<tag>
+    pub fn get_anim(&self, name: &str) -> Option<&AnimData> {
+        self.anims.get(name)
+    }
+
+    // named <sheet>/<slice>
+    pub fn get_hitbox(&self, name: &str) -> Option<Rect> {
+        self.hitboxes.get(name).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{self, Value};
+    use super::SpriteCache;
+
+    fn sheet(names: &[&str], x: f32) -> Value {
+        let frames: Vec<String> = names.iter().map(|name| format!(r#"{{ "name": "{}",
+            "size": {{ "width": 8, "height": 8 }}, "o_size": {{ "width": 8, "height": 8 }},
+            "pos": {{ "x": {}, "y": 0 }}, "offset": {{ "x": 0, "y": 0 }} }}"#, name, x)).collect();
+        serde_json::from_str(&format!("[{}]", frames.join(","))).unwrap()
+    }
 
-        None
+    #[test]
+    fn find_and_get() {
+        let mut sc = SpriteCache::new();
+        sc.add_sheet("a", sheet(&["grass", "rock"], 0.0)).unwrap();
+        sc.add_sheet("b", sheet(&["tree", "rock"], 8.0)).unwrap();
+
+        let rock = sc.find("rock").unwrap();
+        let tree = sc.find("tree").unwrap();
+        assert!(sc.find("water").is_none());
+        assert_eq!(sc.get(tree).sheet, 1);
+        // the name is taken by the first sheet
+        assert_eq!((sc.get(rock).sheet, sc.get(rock).src.x), (0, 0.0));
+
+        // reloading a sheet keeps its handles
+        sc.add_sheet("a", sheet(&["rock"], 16.0)).unwrap();
+        assert_eq!(sc.find("rock"), Some(rock));
+        assert_eq!(sc.get(rock).src.x, 16.0);
     }
 }
@@ -1,47 +1,80 @@
-use sprite::{Sprite, SpriteData, SpriteCache};
-use types::{Point, Size, Color, RenderInfo};
+use std::collections::HashMap;
+
+use sprite::{SpriteHandle, SpriteData, SpriteCache};
+use types::{Point, Size, Depth, Color, RenderInfo};
 use context::Context;
 
+// the frames of an entity, numbered in the order they are listed
 pub struct SpriteManager {
-    sprites: Vec<Sprite>
+    sprites: Vec<SpriteHandle>,
+    indices: HashMap<String, usize>
 }
 
 impl SpriteManager {
     pub fn new(data: &Vec<SpriteData>, sc: &SpriteCache) -> SpriteManager {
         let mut sprites = Vec::new();
+        let mut indices = HashMap::new();
 
         for sprite_data in data {
-            if let Some(sprite) = sc.get_sprite(&sprite_data.name) {
-                sprites.push(sprite);
+            if indices.contains_key(&sprite_data.name) {
+                continue;
+            }
+
+            if let Some(handle) = sc.find(&sprite_data.name) {
+                indices.insert(sprite_data.name.clone(), sprites.len());
+                sprites.push(handle);
             } else {
                 println!("missing player frame: {}", sprite_data.name);
             }
         }
 
         SpriteManager {
-            sprites: sprites
+            sprites,
+            indices
         }
     }
 
     pub fn find_index(&self, name: &str) -> Option<usize> {
-        for (i, sprite) in self.sprites.iter().enumerate() {
-            if sprite.name == name {
-                return Some(i);
+        self.indices.get(name).cloned()
+    }
+
+    pub fn draw(&self, index: usize, pos: Point, layer: u32, ctx: &mut Context) {
+        let info = self.sprites.get(index).and_then(|&h| ctx.get_sprite_cache().render_info(h, pos, layer));
+        match info {
+            Some(info) => ctx.render(info),
+            None => {
+                println!("invalid frame for player: {}", index);
+
+                ctx.render(RenderInfo::rect(pos, Size::new(20.0, 20.0),
+                                            Depth::new(layer, pos.y + 20.0),
+                                            Color::RGB(255, 0, 0)));
             }
         }
-
-        None
     }
+}
 
-    pub fn draw(&self, index: usize, pos: Point, ctx: &mut Context) {
-        if index > self.sprites.len() {
-            println!("invalid frame for player: {}", index);
+#[cfg(test)]
+mod tests {
+    use serde_json;
 
-            ctx.render(RenderInfo::rect(pos, Size::new(20.0, 20.0),
-                                        pos.y, Color::RGB(255, 0, 0)));
-            return;
-        }
+    use sprite::{SpriteCache, SpriteData};
+    use super::SpriteManager;
 
-        self.sprites[index].draw(pos, ctx);
+    #[test]
+    fn duplicates_share_an_index() {
+        let mut sc = SpriteCache::new();
+        sc.add_sheet("hero", serde_json::from_str(r#"{
+            "frames": [
+                { "filename": "a", "frame": { "x": 0, "y": 0, "w": 8, "h": 8 } },
+                { "filename": "b", "frame": { "x": 8, "y": 0, "w": 8, "h": 8 } }
+            ],
+            "meta": {}
+        }"#).unwrap()).unwrap();
+
+        let names = vec![SpriteData::new("b"), SpriteData::new("missing"), SpriteData::new("a"), SpriteData::new("b")];
+        let sprites = SpriteManager::new(&names, &sc);
+        assert_eq!(sprites.sprites, vec![sc.find("b").unwrap(), sc.find("a").unwrap()]);
+        assert_eq!((sprites.find_index("b"), sprites.find_index("a")), (Some(0), Some(1)));
+        assert!(sprites.find_index("missing").is_none());
     }
-}
\ No newline at end of file
+}
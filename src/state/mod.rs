@@ -1,22 +1,71 @@
-mod state_manager;
-pub type StateManager = state_manager::StateManager;
+mod state_machine;
+pub type StateMachine = state_machine::StateMachine;
 
+use types::Direction;
+
+// a state shows a single frame or runs an animation
 #[derive(Serialize, Deserialize, Debug)]
 pub struct StateData {
     name: String,
-    frame: String
+    frame: Option<String>,
+    anim: Option<String>
+}
+
+// moves to to from the state from, or from any state if from is
+// missing, once all conditions hold
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TransitionData {
+    from: Option<String>,
+    to: String,
+    moving: Option<bool>,
+    // up, down, left or right
+    facing: Option<String>,
+    min_speed: Option<f32>,
+    max_speed: Option<f32>,
+    // the state's animation reached its end
+    finished: Option<bool>
+}
+
+// what transitions are checked against, set by the owner every update
+pub struct StateParams {
+    pub moving: bool,
+    pub facing: Direction,
+    // pixels per second
+    pub speed: f32
+}
+
+pub enum Show {
+    Frame(usize),
+    // index in the owner's AnimManager
+    Anim(usize)
 }
 
 pub struct State {
     pub name: String,
-    pub index: usize
+    pub show: Show
+}
+
+pub struct Transition {
+    // any state if None
+    pub from: Option<usize>,
+    pub to: usize,
+    pub cond: Condition
 }
 
-impl State {
-    pub fn new(name: &str, index: usize) -> State {
-        State {
-            name: String::from(name),
-            index: index
-        }
+pub struct Condition {
+    pub moving: Option<bool>,
+    pub facing: Option<Direction>,
+    pub min_speed: Option<f32>,
+    pub max_speed: Option<f32>,
+    pub finished: Option<bool>
+}
+
+impl Condition {
+    pub fn matches(&self, p: &StateParams, finished: bool) -> bool {
+        self.moving.is_none_or(|m| m == p.moving) &&
+        self.facing.is_none_or(|f| f == p.facing) &&
+        self.min_speed.is_none_or(|s| p.speed >= s) &&
+        self.max_speed.is_none_or(|s| p.speed <= s) &&
+        self.finished.is_none_or(|f| f == finished)
     }
-}
\ No newline at end of file
+}
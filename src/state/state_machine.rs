@@ -0,0 +1,292 @@
+use sprite::SpriteManager;
+use animation::AnimManager;
+use state::{State, Show, StateData, StateParams, Transition, TransitionData, Condition};
+use types::Direction;
+
+pub struct StateMachine {
+    states: Vec<State>,
+    transitions: Vec<Transition>,
+    state: usize,
+    anims: AnimManager,
+    // the current state's animation is playing, or held after ending
+    playing: bool,
+    // the current state shows a frame or its animation reached its end
+    done: bool,
+    // sprite index drawn, kept while a state has nothing to show
+    shown: usize
+}
+
+impl StateMachine {
+    pub fn new(data: &[StateData], transitions: &[TransitionData], start: &str,
+               sprites: &SpriteManager, anims: AnimManager) -> StateMachine {
+        let mut states = Vec::new();
+
+        for state_data in data.iter() {
+            let show = match (&state_data.frame, &state_data.anim) {
+                (Some(frame), _) => match sprites.find_index(frame) {
+                    Some(index) => Show::Frame(index),
+                    None => {
+                        println!("frame not found for state {}: {}", state_data.name, frame);
+                        continue;
+                    }
+                },
+                (None, Some(anim)) => match anims.find_anim(anim) {
+                    Some(index) => Show::Anim(index),
+                    None => {
+                        println!("animation not found for state {}: {}", state_data.name, anim);
+                        continue;
+                    }
+                },
+                (None, None) => {
+                    println!("state {} needs a frame or an anim", state_data.name);
+                    continue;
+                }
+            };
+
+            states.push(State { name: state_data.name.clone(), show });
+        }
+
+        let find = |name: &str| {
+            let index = states.iter().position(|s| s.name == name);
+            if index.is_none() { println!("state not found: {}", name); }
+            index
+        };
+
+        let mut resolved = Vec::new();
+        for t in transitions.iter() {
+            let from = match t.from {
+                Some(ref name) => match find(name) {
+                    Some(i) => Some(i),
+                    None => continue
+                },
+                None => None
+            };
+
+            let to = match find(&t.to) {
+                Some(i) => i,
+                None => continue
+            };
+
+            let facing = match t.facing {
+                Some(ref f) => match Direction::from_name(f) {
+                    Some(dir) => Some(dir),
+                    None => {
+                        println!("unknown facing in transition to {}: {}", t.to, f);
+                        continue;
+                    }
+                },
+                None => None
+            };
+
+            resolved.push(Transition {
+                from,
+                to,
+                cond: Condition {
+                    moving: t.moving,
+                    facing,
+                    min_speed: t.min_speed,
+                    max_speed: t.max_speed,
+                    finished: t.finished
+                }
+            });
+        }
+
+        let start = find(start).unwrap_or(0);
+        StateMachine::from_parts(states, resolved, start, anims)
+    }
+
+    fn from_parts(states: Vec<State>, transitions: Vec<Transition>, start: usize, anims: AnimManager) -> StateMachine {
+        let mut sm = StateMachine {
+            states,
+            transitions,
+            state: 0,
+            anims,
+            playing: false,
+            done: true,
+            shown: 0
+        };
+
+        if sm.states.is_empty() {
+            println!("state machine without states");
+        } else {
+            sm.enter(start);
+        }
+        sm
+    }
+
+    // advances the animation, then takes the first transition that holds.
+    // transitions switch instantly
+    pub fn update(&mut self, params: &StateParams, dt: f32) {
+        self.anims.update(dt);
+        if self.anims.finished() {
+            self.done = true;
+        }
+
+        if let Some(next) = self.next_state(params) {
+            self.enter(next);
+        }
+        self.refresh();
+    }
+
+    // sprite index to draw
+    pub fn current(&self) -> usize {
+        self.shown
+    }
+
+    pub fn events(&self) -> &[String] {
+        self.anims.events()
+    }
+
+    fn next_state(&self, params: &StateParams) -> Option<usize> {
+        self.transitions.iter()
+            .filter(|t| t.to != self.state && t.from.is_none_or(|f| f == self.state))
+            .find(|t| t.cond.matches(params, self.done))
+            .map(|t| t.to)
+    }
+
+    fn enter(&mut self, index: usize) {
+        self.state = index;
+
+        self.playing = match self.states[index].show {
+            Show::Anim(anim) => self.anims.play(anim),
+            Show::Frame(_) => {
+                self.anims.stop_anim();
+                false
+            }
+        };
+        // an animation that failed to start counts as finished
+        self.done = !self.playing;
+        if let (&Show::Anim(_), false) = (&self.states[index].show, self.playing) {
+            println!("state {} keeps showing the previous frame", self.states[index].name);
+        }
+        self.refresh();
+    }
+
+    fn refresh(&mut self) {
+        match self.states.get(self.state).map(|s| &s.show) {
+            Some(&Show::Frame(index)) => self.shown = index,
+            Some(&Show::Anim(_)) if self.playing => self.shown = self.anims.current(),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use animation::{AnimManager, AnimData};
+    use sprite::{SpriteCache, SpriteManager, SpriteData};
+    use state::{State, Show, StateData, StateParams, Transition, TransitionData, Condition};
+    use types::Direction;
+    use super::StateMachine;
+
+    fn when(moving: Option<bool>, facing: Option<Direction>) -> Condition {
+        Condition { moving, facing, min_speed: None, max_speed: None, finished: None }
+    }
+
+    #[test]
+    fn first_matching_transition() {
+        let states = vec![
+            State { name: String::from("idle-down"), show: Show::Frame(1) },
+            State { name: String::from("idle-up"), show: Show::Frame(4) },
+            State { name: String::from("walk-up"), show: Show::Frame(3) }
+        ];
+        let transitions = vec![
+            Transition { from: Some(0), to: 1, cond: when(None, Some(Direction::Up)) },
+            Transition { from: None, to: 2, cond: when(Some(true), Some(Direction::Up)) },
+            Transition { from: Some(2), to: 1, cond: when(Some(false), None) }
+        ];
+        let sprites = SpriteManager::new(&Vec::new(), &SpriteCache::new());
+        let anims = AnimManager::new(&Vec::new(), &sprites);
+        let mut sm = StateMachine::from_parts(states, transitions, 0, anims);
+
+        let mut params = StateParams { moving: false, facing: Direction::Down, speed: 0.0 };
+        sm.update(&params, 0.1);
+        assert_eq!(sm.current(), 1);
+
+        params.moving = true;
+        params.facing = Direction::Up;
+        sm.update(&params, 0.1);
+        // only the first matching transition is taken
+        assert_eq!(sm.current(), 4);
+        sm.update(&params, 0.1);
+        assert_eq!(sm.current(), 3);
+
+        params.moving = false;
+        sm.update(&params, 0.1);
+        assert_eq!(sm.current(), 4);
+    }
+
+    // frames hero-0 to hero-2, a once animation over hero-1 and hero-2
+    fn hero(states: &str, transitions: &str) -> StateMachine {
+        let mut sc = SpriteCache::new();
+        let frames: Vec<String> = (0..3).map(|i| format!(r#"{{ "name": "hero-{}",
+            "size": {{ "width": 16, "height": 16 }}, "o_size": {{ "width": 16, "height": 16 }},
+            "pos": {{ "x": {}, "y": 0 }}, "offset": {{ "x": 0, "y": 0 }} }}"#, i, i * 16)).collect();
+        sc.add_sheet("hero", serde_json::from_str(&format!("[{}]", frames.join(","))).unwrap()).unwrap();
+
+        let names = vec![SpriteData::new("hero-0"), SpriteData::new("hero-1"), SpriteData::new("hero-2")];
+        let sprites = SpriteManager::new(&names, &sc);
+
+        let mut attack = AnimData::new("attack", "once");
+        attack.push_frame("hero-1", 0.1);
+        attack.push_frame("hero-2", 0.1);
+        let anims = AnimManager::new(&vec![attack], &sprites);
+
+        let states: Vec<StateData> = serde_json::from_str(states).unwrap();
+        let transitions: Vec<TransitionData> = serde_json::from_str(transitions).unwrap();
+        StateMachine::new(&states, &transitions, "idle", &sprites, anims)
+    }
+
+    #[test]
+    fn from_data() {
+        let mut sm = hero(r#"[
+            { "name": "idle", "frame": "hero-0" },
+            { "name": "attack", "anim": "attack" },
+            { "name": "ghost", "frame": "missing" },
+            { "name": "broken", "anim": "missing" },
+            { "name": "empty" }
+        ]"#, r#"[
+            { "from": "idle", "to": "attack", "facing": "sideways" },
+            { "from": "ghost", "to": "attack" },
+            { "to": "broken" },
+            { "from": "idle", "to": "attack", "moving": true }
+        ]"#);
+
+        // states and transitions that can't be resolved are left out
+        let mut params = StateParams { moving: false, facing: Direction::Down, speed: 0.0 };
+        sm.update(&params, 0.1);
+        assert_eq!(sm.states.len(), 2);
+        assert_eq!(sm.transitions.len(), 1);
+        assert_eq!(sm.current(), 0);
+
+        params.moving = true;
+        sm.update(&params, 0.1);
+        assert_eq!(sm.current(), 1);
+        sm.update(&params, 0.1);
+        assert_eq!(sm.current(), 2);
+    }
+
+    #[test]
+    fn leaves_once_anim_when_finished() {
+        let mut sm = hero(r#"[
+            { "name": "idle", "frame": "hero-0" },
+            { "name": "attack", "anim": "attack" }
+        ]"#, r#"[
+            { "from": "idle", "to": "attack", "moving": true },
+            { "from": "attack", "to": "idle", "finished": true }
+        ]"#);
+
+        let mut params = StateParams { moving: true, facing: Direction::Down, speed: 0.0 };
+        sm.update(&params, 0.1);
+        assert_eq!(sm.current(), 1);
+
+        params.moving = false;
+        sm.update(&params, 0.1);
+        assert_eq!(sm.current(), 2);
+        // the last frame ends the animation and the transition is taken at once
+        sm.update(&params, 0.1);
+        assert_eq!(sm.current(), 0);
+    }
+}
@@ -0,0 +1,160 @@
+use std::cell::{Cell, RefCell};
+use std::mem;
+use std::rc::Rc;
+
+use sdl2::render::Texture;
+
+use types::{Point, Size, Rect};
+
+use super::tile::Tile;
+
+// in tiles
+pub const CHUNK_SIZE: usize = 32;
+
+// static tiles are baked into one texture, animated ones drawn on top
+pub struct Chunk {
+    // first map cell and size, in cells
+    pub x: i32,
+    pub y: i32,
+    pub width: usize,
+    pub height: usize,
+    // row major cells of the chunk
+    tiles: Vec<Option<Tile>>,
+    // indices of the occupied cells, split once at load
+    statics: Vec<usize>,
+    animated: Vec<usize>,
+    tex: RefCell<Option<Rc<RefCell<Texture>>>>,
+    baked: Cell<bool>,
+}
+
+impl Chunk {
+    pub fn new(x: i32, y: i32, width: usize, height: usize, tiles: Vec<Option<Tile>>) -> Chunk {
+        let mut chunk = Chunk {
+            x,
+            y,
+            width,
+            height,
+            tiles,
+            statics: Vec::new(),
+            animated: Vec::new(),
+            tex: RefCell::new(None),
+            baked: Cell::new(false)
+        };
+        chunk.split_tiles();
+        chunk
+    }
+
+    fn split_tiles(&mut self) {
+        self.statics.clear();
+        self.animated.clear();
+        for (i, tile) in self.tiles.iter().enumerate() {
+            match *tile {
+                Some(ref t) if t.is_animated() => self.animated.push(i),
+                Some(_) => self.statics.push(i),
+                None => {}
+            }
+        }
+    }
+
+    pub fn bounds(&self, tilesize: Size) -> Rect {
+        Rect {
+            pos: Point::new(self.x as f32 * tilesize.w, self.y as f32 * tilesize.h),
+            size: Size::new(self.width as f32 * tilesize.w, self.height as f32 * tilesize.h)
+        }
+    }
+
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        let (x, y) = (x - self.x, y - self.y);
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return None;
+        }
+
+        Some(y as usize * self.width + x as usize)
+    }
+
+    pub fn tile(&self, x: i32, y: i32) -> Option<&Tile> {
+        self.index(x, y).and_then(|i| self.tiles[i].as_ref())
+    }
+
+    // grows the chunk to cover the cell, it is baked again on the next draw
+    pub fn set_tile(&mut self, x: i32, y: i32, tile: Option<Tile>) {
+        let i = match self.index(x, y) {
+            Some(i) => i,
+            None => {
+                self.grow_to(x, y);
+                self.index(x, y).unwrap()
+            }
+        };
+
+        self.tiles[i] = tile;
+        self.split_tiles();
+        self.invalidate();
+    }
+
+    fn grow_to(&mut self, x: i32, y: i32) {
+        let (x0, y0) = (self.x.min(x), self.y.min(y));
+        let width = ((self.x + self.width as i32).max(x + 1) - x0) as usize;
+        let height = ((self.y + self.height as i32).max(y + 1) - y0) as usize;
+
+        let tiles = (0..width * height).map(|_| None).collect();
+        let old = mem::replace(&mut self.tiles, tiles);
+        for (i, tile) in old.into_iter().enumerate() {
+            let cx = self.x + (i % self.width) as i32 - x0;
+            let cy = self.y + (i / self.width) as i32 - y0;
+            self.tiles[cy as usize * width + cx as usize] = tile;
+        }
+
+        self.x = x0;
+        self.y = y0;
+        self.width = width;
+        self.height = height;
+    }
+
+    pub fn invalidate(&mut self) {
+        *self.tex.get_mut() = None;
+        self.baked.set(false);
+    }
+
+    pub fn static_tiles<'a>(&'a self) -> impl Iterator<Item=&'a Tile> + 'a {
+        self.statics.iter().filter_map(move |&i| self.tiles[i].as_ref())
+    }
+
+    pub fn animated_tiles<'a>(&'a self) -> impl Iterator<Item=&'a Tile> + 'a {
+        self.animated.iter().filter_map(move |&i| self.tiles[i].as_ref())
+    }
+
+    pub fn has_static_tiles(&self) -> bool {
+        !self.statics.is_empty()
+    }
+
+    // a baked chunk without a texture is drawn tile by tile
+    pub fn is_baked(&self) -> bool {
+        self.baked.get()
+    }
+
+    pub fn texture(&self) -> Option<Rc<RefCell<Texture>>> {
+        self.tex.borrow().clone()
+    }
+
+    pub fn store(&self, tex: Option<Rc<RefCell<Texture>>>) {
+        *self.tex.borrow_mut() = tex;
+        self.baked.set(true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Chunk;
+
+    #[test]
+    fn set_tile_grows_and_invalidates() {
+        let mut chunk = Chunk::new(2, 2, 1, 1, vec![None]);
+        chunk.store(None);
+        assert!(chunk.is_baked());
+
+        chunk.set_tile(0, 4, None);
+        assert!(!chunk.is_baked());
+        assert_eq!((chunk.x, chunk.y, chunk.width, chunk.height), (0, 2, 3, 3));
+        assert!(chunk.tile(0, 4).is_none());
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::HashSet;
+
+use types::{Size, Rect};
+
+use super::layer::{FlatLayer, CellBlock};
+use super::tileset::{Tileset, Imageset, find_tileset};
+use super::tile::TileFlags;
+use super::object::{MapObject, ObjectKind};
+
+pub struct CollisionMap {
+    // cell rectangle of the map as (x0, y0, x1, y1), negative on infinite maps
+    bounds: (i32, i32, i32, i32),
+    tilesize: Size,
+    // only the solid cells, infinite maps can be far apart
+    cells: HashSet<(i32, i32)>,
+    shapes: Vec<Rect>,
+}
+
+impl CollisionMap {
+    pub fn new(bounds: (i32, i32, i32, i32), tilesize: Size) -> CollisionMap {
+        CollisionMap {
+            bounds,
+            tilesize,
+            cells: HashSet::new(),
+            shapes: Vec::new()
+        }
+    }
+
+    // every tile of a collision layer is solid,
+    // elsewhere only tiles with a solid tile property
+    pub fn add_tile_layer(&mut self, layer: &FlatLayer, blocks: &[CellBlock], tilesets: &[Tileset]) {
+        let collision_layer = layer.is_collision_layer();
+
+        for (x, y, raw) in blocks.iter().flat_map(|b| b.cells()) {
+            let (gid, _) = TileFlags::split_gid(raw);
+            if gid == 0 { continue; }
+
+            let solid = collision_layer || match find_tileset(tilesets, gid) {
+                Some(ts) => ts.tile_flag(gid, "solid"),
+                None => false
+            };
+
+            if solid {
+                self.set_solid(x, y);
+            }
+        }
+    }
+
+    // every rect and tile object of a collision layer is solid, elsewhere those
+    // of type solid, with a solid property or showing a solid tile
+    pub fn add_object_layer(&mut self, layer: &FlatLayer, tilesets: &[Tileset], imagesets: &[Imageset]) {
+        let collision_layer = layer.is_collision_layer();
+
+        for od in layer.data.objects() {
+            let object = MapObject::new(od);
+            let (shape, solid_tile) = match object.kind {
+                ObjectKind::Tile(gid) => (true, match find_tileset(tilesets, gid) {
+                    Some(ts) => ts.tile_flag(gid, "solid"),
+                    None => imagesets.iter().any(|is| is.tile_flag(gid, "solid"))
+                }),
+                _ => (od.is_rect(), false)
+            };
+
+            if !shape || !(collision_layer || od.is_solid() || solid_tile) {
+                continue;
+            }
+
+            // shapes are axis aligned boxes
+            if object.rotation != 0.0 {
+                println!("solid object {} can't be rotated", object.id);
+                continue;
+            }
+
+            self.shapes.push(object.bounds());
+        }
+    }
+
+    pub fn set_solid(&mut self, x: i32, y: i32) {
+        if self.contains(x, y) {
+            self.cells.insert((x, y));
+        }
+    }
+
+    pub fn is_solid(&self, x: i32, y: i32) -> bool {
+        // everything outside the map blocks movement
+        !self.contains(x, y) || self.cells.contains(&(x, y))
+    }
+
+    fn contains(&self, x: i32, y: i32) -> bool {
+        let (x0, y0, x1, y1) = self.bounds;
+        x >= x0 && y >= y0 && x < x1 && y < y1
+    }
+
+    pub fn is_blocked(&self, r: &Rect) -> bool {
+        let min_x = (r.left() / self.tilesize.w).floor() as i32;
+        let min_y = (r.top() / self.tilesize.h).floor() as i32;
+        let max_x = ((r.right() / self.tilesize.w).ceil() as i32) - 1;
+        let max_y = ((r.bottom() / self.tilesize.h).ceil() as i32) - 1;
+
+        for y in min_y..max_y + 1 {
+            for x in min_x..max_x + 1 {
+                if self.is_solid(x, y) { return true; }
+            }
+        }
+
+        self.shapes.iter().any(|s| s.intersects(r))
+    }
+}
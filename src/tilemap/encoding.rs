@@ -0,0 +1,223 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{Cursor, Read};
+
+use serde_json::Value;
+
+#[derive(Debug)]
+pub enum DecodeError {
+    MissingData,
+    UnsupportedEncoding(String),
+    UnsupportedCompression(String),
+    InvalidBase64(String),
+    InvalidCsv(String),
+    InvalidGid(String),
+    Decompress(String),
+    SizeMismatch { expected: usize, actual: usize },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::MissingData => write!(f, "layer has no tile data"),
+            DecodeError::UnsupportedEncoding(ref e) => write!(f, "unsupported encoding: {}", e),
+            DecodeError::UnsupportedCompression(ref c) => write!(f, "unsupported compression: {}", c),
+            DecodeError::InvalidBase64(ref e) => write!(f, "invalid base64 data: {}", e),
+            DecodeError::InvalidCsv(ref v) => write!(f, "invalid csv value: {}", v),
+            DecodeError::InvalidGid(ref v) => write!(f, "invalid gid: {}", v),
+            DecodeError::Decompress(ref e) => write!(f, "could not decompress data: {}", e),
+            DecodeError::SizeMismatch { expected, actual } =>
+                write!(f, "expected {} tiles, got {}", expected, actual),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+// data is a base64 or csv string, see encoding, or a json array
+pub fn decode_gids(data: Option<&Value>,
+                   encoding: Option<&str>,
+                   compression: Option<&str>,
+                   expected: usize) -> Result<Vec<u32>, DecodeError> {
+    let data = match data {
+        Some(data) => data,
+        None => return Err(DecodeError::MissingData)
+    };
+
+    let gids = match (data, encoding) {
+        (Value::Array(values), None) => decode_array(values)?,
+        (Value::String(s), Some("base64")) => decode_base64(s, compression)?,
+        (Value::String(s), Some("csv")) => decode_csv(s)?,
+        (_, Some(e)) => return Err(DecodeError::UnsupportedEncoding(String::from(e))),
+        (_, None) => return Err(DecodeError::UnsupportedEncoding(String::from("none")))
+    };
+
+    if gids.len() != expected {
+        return Err(DecodeError::SizeMismatch { expected, actual: gids.len() });
+    }
+
+    Ok(gids)
+}
+
+fn decode_array(values: &[Value]) -> Result<Vec<u32>, DecodeError> {
+    let mut gids = Vec::with_capacity(values.len());
+    for v in values {
+        match v.as_u64() {
+            Some(gid) if gid <= u32::MAX as u64 => gids.push(gid as u32),
+            _ => return Err(DecodeError::InvalidGid(v.to_string()))
+        }
+    }
+
+    Ok(gids)
+}
+
+fn decode_csv(s: &str) -> Result<Vec<u32>, DecodeError> {
+    let mut gids = Vec::new();
+    for field in s.split(',') {
+        let field = field.trim();
+        if field.is_empty() { continue; }
+
+        match field.parse::<u32>() {
+            Ok(gid) => gids.push(gid),
+            Err(_) => return Err(DecodeError::InvalidCsv(String::from(field)))
+        }
+    }
+
+    Ok(gids)
+}
+
+fn decode_base64(s: &str, compression: Option<&str>) -> Result<Vec<u32>, DecodeError> {
+    // tmx data may be wrapped over several lines
+    let s: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+    let bytes = match ::base64::decode(&s) {
+        Ok(bytes) => bytes,
+        Err(err) => return Err(DecodeError::InvalidBase64(err.to_string()))
+    };
+
+    let bytes = match compression {
+        None | Some("") => bytes,
+        Some("zlib") => {
+            let mut out = Vec::new();
+            ::flate2::read::ZlibDecoder::new(&bytes[..])
+                .read_to_end(&mut out)
+                .map_err(|e| DecodeError::Decompress(e.to_string()))?;
+            out
+        },
+        Some("gzip") => {
+            let mut out = Vec::new();
+            ::flate2::read::GzDecoder::new(&bytes[..])
+                .read_to_end(&mut out)
+                .map_err(|e| DecodeError::Decompress(e.to_string()))?;
+            out
+        },
+        Some("zstd") => {
+            ::zstd::stream::decode_all(&bytes[..])
+                .map_err(|e| DecodeError::Decompress(e.to_string()))?
+        },
+        Some(c) => return Err(DecodeError::UnsupportedCompression(String::from(c)))
+    };
+
+    if bytes.len() % 4 != 0 {
+        return Err(DecodeError::InvalidBase64(format!("{} bytes is not a multiple of 4", bytes.len())));
+    }
+
+    // data should be interpreted as a u32 array
+    // with little endian byte ordering
+    use byteorder::{LittleEndian, ReadBytesExt};
+    let mut gids = Vec::with_capacity(bytes.len() / 4);
+    for field in bytes.chunks(4) {
+        let mut c = Cursor::new(field);
+        gids.push(c.read_u32::<LittleEndian>().unwrap());
+    }
+
+    Ok(gids)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use serde_json::Value;
+    use flate2::Compression;
+    use flate2::write::{ZlibEncoder, GzEncoder};
+
+    use super::{decode_gids, DecodeError};
+
+    const GIDS: [u32; 4] = [1, 0, 300, 0x80000002];
+
+    fn bytes() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for gid in GIDS.iter() {
+            bytes.extend_from_slice(&[*gid as u8, (*gid >> 8) as u8, (*gid >> 16) as u8, (*gid >> 24) as u8]);
+        }
+        bytes
+    }
+
+    fn base64(bytes: &[u8]) -> Value {
+        Value::from(::base64::encode(bytes))
+    }
+
+    #[test]
+    fn plain_encodings() {
+        let array = Value::Array(GIDS.iter().map(|&g| Value::from(g as u64)).collect());
+        assert_eq!(decode_gids(Some(&array), None, None, 4).unwrap(), GIDS.to_vec());
+
+        let csv = Value::from("1,0,\n300,2147483650\n");
+        assert_eq!(decode_gids(Some(&csv), Some("csv"), None, 4).unwrap(), GIDS.to_vec());
+
+        assert_eq!(decode_gids(Some(&base64(&bytes())), Some("base64"), None, 4).unwrap(), GIDS.to_vec());
+    }
+
+    #[test]
+    fn compressed_base64() {
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(&bytes()).unwrap();
+        let zlib = base64(&zlib.finish().unwrap());
+        assert_eq!(decode_gids(Some(&zlib), Some("base64"), Some("zlib"), 4).unwrap(), GIDS.to_vec());
+
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(&bytes()).unwrap();
+        let gzip = base64(&gzip.finish().unwrap());
+        assert_eq!(decode_gids(Some(&gzip), Some("base64"), Some("gzip"), 4).unwrap(), GIDS.to_vec());
+
+        let zstd = base64(&::zstd::stream::encode_all(&bytes()[..], 0).unwrap());
+        assert_eq!(decode_gids(Some(&zstd), Some("base64"), Some("zstd"), 4).unwrap(), GIDS.to_vec());
+    }
+
+    #[test]
+    fn errors() {
+        let data = base64(&bytes());
+        match decode_gids(None, None, None, 4) {
+            Err(DecodeError::MissingData) => {},
+            r => panic!("expected missing data, got {:?}", r)
+        }
+        match decode_gids(Some(&Value::from("!!!")), Some("base64"), None, 4) {
+            Err(DecodeError::InvalidBase64(_)) => {},
+            r => panic!("expected invalid base64, got {:?}", r)
+        }
+        match decode_gids(Some(&base64(&bytes()[..6])), Some("base64"), None, 4) {
+            Err(DecodeError::InvalidBase64(_)) => {},
+            r => panic!("expected a partial gid to fail, got {:?}", r)
+        }
+        match decode_gids(Some(&data), Some("base64"), None, 5) {
+            Err(DecodeError::SizeMismatch { expected: 5, actual: 4 }) => {},
+            r => panic!("expected a size mismatch, got {:?}", r)
+        }
+        match decode_gids(Some(&data), Some("base64"), Some("lzma"), 4) {
+            Err(DecodeError::UnsupportedCompression(ref c)) if c == "lzma" => {},
+            r => panic!("expected an unsupported compression, got {:?}", r)
+        }
+        match decode_gids(Some(&data), Some("base64"), Some("zlib"), 4) {
+            Err(DecodeError::Decompress(_)) => {},
+            r => panic!("expected a decompression error, got {:?}", r)
+        }
+        match decode_gids(Some(&data), Some("hex"), None, 4) {
+            Err(DecodeError::UnsupportedEncoding(ref e)) if e == "hex" => {},
+            r => panic!("expected an unsupported encoding, got {:?}", r)
+        }
+        match decode_gids(Some(&Value::from("1,x")), Some("csv"), None, 2) {
+            Err(DecodeError::InvalidCsv(ref v)) if v == "x" => {},
+            r => panic!("expected invalid csv, got {:?}", r)
+        }
+    }
+}
@@ -1,9 +1,19 @@
-use types::{Point, Size};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use sdl2::render::Texture;
+use serde_json::Value;
+
+use types::{Point, Size, Vec2, Color, Depth, RenderInfo, RenderStyle, Blend};
 use context::Context;
 
 use super::tileset::{Tileset, Imageset};
 use super::tile::Tile;
-use super::object::{ObjectData, TileObject};
+use super::property::{Properties, parse_color};
+use super::chunk::{Chunk, CHUNK_SIZE};
+use super::object::{ObjectData, TileObject, MapObject};
+use super::encoding::{self, DecodeError};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct LayerData {
@@ -12,117 +22,503 @@ pub struct LayerData {
 
     // common fields
     pub name: String,
+    #[serde(default)]
     height: u16,
+    #[serde(default)]
     width: u16,
     opacity: f32,
     visible: bool,
+    #[serde(default)]
+    offsetx: f32,
+    #[serde(default)]
+    offsety: f32,
+    tintcolor: Option<String>,
+    parallaxx: Option<f32>,
+    parallaxy: Option<f32>,
+    #[serde(default)]
     x: u16,
+    #[serde(default)]
     y: u16,
+    properties: Option<Value>,
+    propertytypes: Option<HashMap<String, String>>,
 
     // tilelayer fields
     encoding: Option<String>,
-    data: Option<String>,
+    compression: Option<String>,
+    data: Option<Value>,
+    // infinite maps only
+    chunks: Option<Vec<ChunkData>>,
 
     // objectlayer fields
     draworder: Option<String>,
     objects: Option<Vec<ObjectData>>,
+
+    // group fields
+    layers: Option<Vec<LayerData>>,
+
+    // imagelayer fields
+    image: Option<String>,
+    #[serde(default)]
+    repeatx: bool,
+    #[serde(default)]
+    repeaty: bool,
+}
+
+// with the offset, opacity, tint and visibility of its groups folded in
+pub struct FlatLayer<'a> {
+    pub data: &'a LayerData,
+    // the layer's own, groups don't pass theirs on
+    pub properties: Properties,
+    pub style: RenderStyle,
+    pub visible: bool,
+}
+
+impl<'a> FlatLayer<'a> {
+    // named collision or with a collision property, never drawn
+    pub fn is_collision_layer(&self) -> bool {
+        self.data.name == "collision" || self.properties.flag("collision")
+    }
+}
+
+// groups are replaced by their children
+pub fn flatten_layers(layers: &[LayerData]) -> Vec<FlatLayer<'_>> {
+    let mut flat = Vec::new();
+    flatten_into(layers, &RenderStyle::new(), true, &mut flat);
+    flat
+}
+
+fn flatten_into<'a>(layers: &'a [LayerData], parent: &RenderStyle,
+                    visible: bool, flat: &mut Vec<FlatLayer<'a>>) {
+    for layer in layers {
+        let properties = layer.properties();
+        let style = layer.style(&properties).within(parent);
+        let visible = visible && layer.visible;
+
+        if layer.is_group() {
+            if let Some(ref children) = layer.layers {
+                flatten_into(children, &style, visible, flat);
+            }
+            continue;
+        }
+
+        flat.push(FlatLayer {
+            data: layer,
+            properties,
+            style,
+            visible
+        });
+    }
+}
+
+// part of a tile layer of an infinite map, in cells
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChunkData {
+    x: i32,
+    y: i32,
+    width: u16,
+    height: u16,
+    data: Value,
 }
 
 impl LayerData {
+    pub fn is_tile_layer(&self) -> bool {
+        self.layer_type == "tilelayer"
+    }
+
     pub fn is_object_layer(&self) -> bool {
         self.layer_type == "objectgroup"
     }
+
+    pub fn is_image_layer(&self) -> bool {
+        self.layer_type == "imagelayer"
+    }
+
+    pub fn is_group(&self) -> bool {
+        self.layer_type == "group"
+    }
+
+    fn properties(&self) -> Properties {
+        Properties::from_json(self.properties.as_ref(), self.propertytypes.as_ref())
+    }
+
+    // as (x0, y0, x1, y1), None for infinite layers without chunks.
+    // chunks of infinite maps can start at negative cells
+    pub fn cell_bounds(&self) -> Option<(i32, i32, i32, i32)> {
+        let chunks = match self.chunks {
+            Some(ref chunks) => chunks,
+            None => {
+                let (x, y) = (self.x as i32, self.y as i32);
+                return Some((x, y, x + self.width as i32, y + self.height as i32));
+            }
+        };
+
+        chunks.iter().fold(None, |bounds, c| {
+            let (x1, y1) = (c.x + c.width as i32, c.y + c.height as i32);
+            Some(match bounds {
+                Some((bx0, by0, bx1, by1)) => (bx0.min(c.x), by0.min(c.y), bx1.max(x1), by1.max(y1)),
+                None => (c.x, c.y, x1, y1)
+            })
+        })
+    }
+
+    // the blend property is alpha, additive or multiply
+    fn style(&self, properties: &Properties) -> RenderStyle {
+        let tint = self.tintcolor.as_ref()
+            .and_then(|c| parse_color(c))
+            .unwrap_or(Color::RGB(255, 255, 255));
+
+        // the tint's alpha fades the layer like its opacity does
+        let (r, g, b, a) = tint.rgba();
+        let alpha = self.opacity.clamp(0.0, 1.0) * a as f32;
+
+        RenderStyle {
+            alpha: alpha.round() as u8,
+            color_mod: Color::RGB(r, g, b),
+            blend: match properties.get_str("blend") {
+                Some("additive") => Blend::Additive,
+                Some("multiply") => Blend::Multiply,
+                _ => Blend::Alpha
+            },
+            offset: Vec2::new(self.offsetx, self.offsety),
+            parallax: Vec2::new(self.parallaxx.unwrap_or(1.0), self.parallaxy.unwrap_or(1.0))
+        }
+    }
+
+    pub fn draw_order(&self) -> DrawOrder {
+        match self.draworder.as_deref() {
+            Some("index") => DrawOrder::Index,
+            _ => DrawOrder::TopDown
+        }
+    }
+
+    pub fn objects(&self) -> &[ObjectData] {
+        match self.objects {
+            Some(ref objects) => objects,
+            None => &[]
+        }
+    }
+
+    // one block for finite layers, one per chunk for infinite ones
+    pub fn decode_blocks(&self) -> Result<Vec<CellBlock>, DecodeError> {
+        let encoding = self.encoding.as_deref();
+        let compression = self.compression.as_deref();
+
+        let chunks = match self.chunks {
+            Some(ref chunks) => chunks,
+            None => {
+                let (width, height) = (self.width as usize, self.height as usize);
+                let gids = encoding::decode_gids(self.data.as_ref(), encoding, compression, width * height)?;
+                return Ok(vec![CellBlock {
+                    x: self.x as i32,
+                    y: self.y as i32,
+                    width,
+                    gids
+                }]);
+            }
+        };
+
+        let mut blocks = Vec::with_capacity(chunks.len());
+        for c in chunks {
+            let (width, height) = (c.width as usize, c.height as usize);
+            blocks.push(CellBlock {
+                x: c.x,
+                y: c.y,
+                width,
+                gids: encoding::decode_gids(Some(&c.data), encoding, compression, width * height)?
+            });
+        }
+
+        Ok(blocks)
+    }
+}
+
+pub struct CellBlock {
+    // first cell
+    pub x: i32,
+    pub y: i32,
+    pub width: usize,
+    // row major, with flip flags
+    pub gids: Vec<u32>,
+}
+
+impl CellBlock {
+    // as (x, y, gid)
+    pub fn cells<'a>(&'a self) -> impl Iterator<Item=(i32, i32, u32)> + 'a {
+        self.gids.iter().enumerate().map(move |(i, &gid)| {
+            (self.x + (i % self.width) as i32, self.y + (i / self.width) as i32, gid)
+        })
+    }
 }
 
-//TODO implement opacity
 pub struct TileLayer {
-    _opacity: f32,
+    pub name: String,
+    style: RenderStyle,
     visible: bool,
-    tiles: Vec<Tile>,
+    tilesize: Size,
+    // off for the entity layer, whose tiles sort with the entities
+    bake: bool,
+    // only chunks with tiles, by row
+    chunks: Vec<Chunk>,
+    // chunk coordinate to index into chunks
+    chunk_index: HashMap<(i32, i32), usize>,
 }
 
 impl TileLayer {
-    pub fn new(data: &LayerData,
+    pub fn new(layer: &FlatLayer,
+               blocks: &[CellBlock],
                tilesets: &Vec<Tileset>,
-               tilesize: &Size) -> TileLayer {
-        // decode data from base64
-        use base64::decode;
-        let bytes = decode(&data.data.as_ref().unwrap()).unwrap();
-        let size = (data.width * data.height) as usize;
-        assert!(size * 4 == bytes.len());
-
-        // data should be interpreted as a u32 array
-        // with little endian byte ordering
-        use byteorder::{LittleEndian, ReadBytesExt};
-        use std::io::Cursor;
-        let mut fields = Vec::with_capacity(size);
-        for field in bytes.chunks(4) {
-            let mut c = Cursor::new(field);
-            let gid = c.read_u32::<LittleEndian>().unwrap();
-            fields.push(gid);
-        }
-
-        let mut tiles = Vec::with_capacity(size);
-        let mut counter = 0;
-        for y in 0..data.height {
-            for x in 0..data.width {
-                if counter >= fields.len() { break; }
-                let pos = Point::new(x as f32 * tilesize.w,
-                                     y as f32 * tilesize.h);
-
-                if let Some(tile) = Tile::from_gid(fields[counter],
-                                                   &tilesets,
-                                                   pos,
-                                                   tilesize.clone()) {
-                    tiles.push(tile);
-                }
-                counter += 1;
+               tilesize: &Size,
+               bake: bool) -> TileLayer {
+        // sort the occupied cells into CHUNK_SIZE squares,
+        // so far apart parts of a layer stay cheap
+        let size = CHUNK_SIZE as i32;
+        let mut cells: HashMap<_, Vec<_>> = HashMap::new();
+        for block in blocks {
+            for (x, y, gid) in block.cells().filter(|&(_, _, gid)| gid != 0) {
+                cells.entry((x.div_euclid(size), y.div_euclid(size)))
+                    .or_default()
+                    .push((x, y, gid));
+            }
+        }
+
+        let mut keys: Vec<(i32, i32)> = cells.keys().cloned().collect();
+        keys.sort_by_key(|&(x, y)| (y, x));
+
+        let mut chunks = Vec::with_capacity(keys.len());
+        let mut chunk_index = HashMap::new();
+        for key in keys {
+            let cells = &cells[&key];
+            let x0 = cells.iter().map(|c| c.0).min().unwrap();
+            let y0 = cells.iter().map(|c| c.1).min().unwrap();
+            let width = (cells.iter().map(|c| c.0).max().unwrap() - x0 + 1) as usize;
+            let height = (cells.iter().map(|c| c.1).max().unwrap() - y0 + 1) as usize;
+
+            let mut tiles: Vec<Option<Tile>> = (0..width * height).map(|_| None).collect();
+            for &(x, y, gid) in cells {
+                let pos = Point::new(x as f32 * tilesize.w, y as f32 * tilesize.h);
+                tiles[(y - y0) as usize * width + (x - x0) as usize] =
+                    Tile::from_gid(gid, tilesets, pos, *tilesize);
             }
+
+            chunk_index.insert(key, chunks.len());
+            chunks.push(Chunk::new(x0, y0, width, height, tiles));
         }
 
         TileLayer {
-            _opacity: data.opacity,
-            visible: data.visible,
-            tiles: tiles
+            name: layer.data.name.clone(),
+            style: layer.style,
+            visible: layer.visible,
+            tilesize: *tilesize,
+            bake,
+            chunks,
+            chunk_index
         }
     }
 
-    pub fn draw(&self, z: f32, ctx: &mut Context) {
+    // without flip flags
+    pub fn gid_at(&self, x: i32, y: i32) -> Option<u32> {
+        let size = CHUNK_SIZE as i32;
+        self.chunk_index.get(&(x.div_euclid(size), y.div_euclid(size)))
+            .and_then(|&i| self.chunks[i].tile(x, y))
+            .map(|t| t.gid)
+    }
+
+    // gid 0 clears the cell
+    pub fn set_tile(&mut self, x: i32, y: i32, gid: u32, tilesets: &Vec<Tileset>) {
+        let pos = Point::new(x as f32 * self.tilesize.w, y as f32 * self.tilesize.h);
+        let tile = Tile::from_gid(gid, tilesets, pos, self.tilesize);
+
+        let size = CHUNK_SIZE as i32;
+        let key = (x.div_euclid(size), y.div_euclid(size));
+        match self.chunk_index.get(&key) {
+            Some(&i) => self.chunks[i].set_tile(x, y, tile),
+            None => if tile.is_some() {
+                self.chunk_index.insert(key, self.chunks.len());
+                self.chunks.push(Chunk::new(x, y, 1, 1, vec![tile]));
+            }
+        }
+    }
+
+    // bakes every chunk up front so the first frames don't have to
+    pub fn bake(&self, ctx: &mut Context) {
+        if !self.bake { return; }
+        for chunk in &self.chunks {
+            self.chunk_texture(chunk, ctx);
+        }
+    }
+
+    pub fn draw(&self, layer: u32, time: f32, ctx: &mut Context) {
         if !self.visible { return; }
-        for tile in &self.tiles {
-            tile.draw(z, ctx);
+
+        let view = ctx.layer_view(self.style.parallax);
+        let mut culled = 0;
+        let offset = Point::new(self.style.offset.x, self.style.offset.y);
+        for chunk in &self.chunks {
+            let bounds = chunk.bounds(self.tilesize);
+            if !bounds.offset(offset).intersects(&view) {
+                culled += 1;
+                continue;
+            }
+
+            if !self.bake {
+                for tile in chunk.static_tiles().chain(chunk.animated_tiles()) {
+                    ctx.render(tile.render_info(layer, time).styled(&self.style));
+                }
+                continue;
+            }
+
+            // chunks are baked plain, the layer's style is applied when drawing
+            match self.chunk_texture(chunk, ctx) {
+                Some(tex) => {
+                    let depth = Depth::new(layer, bounds.bottom());
+                    ctx.render(RenderInfo::texture(bounds.pos, bounds.size,
+                                                   Point::new(0.0, 0.0), bounds.size,
+                                                   depth, tex)
+                               .styled(&self.style));
+                },
+                // no render target support, draw tile by tile
+                None => for tile in chunk.static_tiles() {
+                    ctx.render(tile.render_info(layer, time).styled(&self.style));
+                }
+            }
+
+            for tile in chunk.animated_tiles() {
+                ctx.render(tile.render_info(layer, time).styled(&self.style));
+            }
+        }
+
+        ctx.add_culled_chunks(culled);
+    }
+
+    // bakes on first use, None if there is nothing to bake or baking failed
+    fn chunk_texture(&self, chunk: &Chunk, ctx: &mut Context) -> Option<Rc<RefCell<Texture>>> {
+        if !chunk.is_baked() {
+            let tex = if chunk.has_static_tiles() {
+                let bounds = chunk.bounds(self.tilesize);
+                let items: Vec<RenderInfo> = chunk.static_tiles()
+                    .map(|t| t.render_info(0, 0.0))
+                    .collect();
+                ctx.bake(bounds.size, bounds.pos, &items)
+            } else {
+                None
+            };
+            chunk.store(tex);
         }
+
+        chunk.texture()
     }
 }
 
-//TODO implement opacity
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum DrawOrder {
+    // sorted by y, together with the entities of the layer
+    TopDown,
+    // in the order of the layer's object list
+    Index,
+}
+
 pub struct ObjectLayer {
-    _opacity: f32,
+    style: RenderStyle,
     visible: bool,
-    objects: Vec<TileObject>,
+    draw_order: DrawOrder,
+    // every object of the layer
+    pub objects: Vec<MapObject>,
+    // the drawable subset
+    tile_objects: Vec<TileObject>,
 }
 
 impl ObjectLayer {
-    pub fn new(data: &LayerData, imagesets: &Vec<Imageset>) -> ObjectLayer {
+    pub fn new(layer: &FlatLayer, imagesets: &[Imageset], tilesets: &[Tileset]) -> ObjectLayer {
+        let data = layer.data;
         let mut objects = Vec::new();
+        let mut tile_objects = Vec::new();
 
-        for od in data.objects.as_ref().unwrap().iter() {
-            if let Some(o) = TileObject::new(od, imagesets) {
-                objects.push(o);
+        for od in data.objects() {
+            let object = MapObject::new(od);
+            // hidden objects stay queryable, they just aren't drawn
+            if object.visible {
+                if let Some(o) = TileObject::new(od, imagesets, tilesets) {
+                    tile_objects.push(o);
+                }
             }
+            objects.push(object);
         }
 
         ObjectLayer {
-            _opacity: data.opacity,
-            visible: data.visible,
-            objects: objects
+            style: layer.style,
+            visible: layer.visible,
+            draw_order: data.draw_order(),
+            objects,
+            tile_objects
         }
     }
 
-    pub fn draw(&self, ctx: &mut Context) {
+    pub fn draw(&self, layer: u32, ctx: &mut Context) {
         if !self.visible { return; }
-        for object in &self.objects {
-            object.draw(ctx);
+        for (i, object) in self.tile_objects.iter().enumerate() {
+            let depth = match self.draw_order {
+                DrawOrder::TopDown => Depth::new(layer, object.sort_y()),
+                DrawOrder::Index => Depth::in_order(layer, i as u32)
+            };
+            object.draw(depth, &self.style, ctx);
+        }
+    }
+}
+
+// optionally repeated to fill the view
+pub struct ImageLayer {
+    style: RenderStyle,
+    visible: bool,
+    tex: Rc<RefCell<Texture>>,
+    size: Size,
+    repeat_x: bool,
+    repeat_y: bool,
+}
+
+impl ImageLayer {
+    pub fn new(layer: &FlatLayer, ctx: &mut Context) -> Result<ImageLayer, String> {
+        let data = layer.data;
+        let image = match data.image {
+            Some(ref image) if !image.is_empty() => image,
+            _ => return Err(String::from("layer has no image"))
+        };
+
+        let tex = ctx.load_texture(image)?;
+        let query = tex.borrow().query();
+
+        Ok(ImageLayer {
+            style: layer.style,
+            visible: layer.visible,
+            tex,
+            size: Size::new(query.width as f32, query.height as f32),
+            repeat_x: data.repeatx,
+            repeat_y: data.repeaty
+        })
+    }
+
+    pub fn draw(&self, layer: u32, ctx: &mut Context) {
+        if !self.visible || self.size.w <= 0.0 || self.size.h <= 0.0 { return; }
+
+        // the view as seen by this layer, relative to the image origin
+        let view = ctx.layer_view(self.style.parallax);
+        let left = view.left() - self.style.offset.x;
+        let top = view.top() - self.style.offset.y;
+
+        let steps = |repeat: bool, start: f32, extent: f32, step: f32| {
+            if !repeat { return vec![0.0]; }
+            let first = (start / step).floor() as i32;
+            let last = ((start + extent) / step).ceil() as i32;
+            (first..last).map(|i| i as f32 * step).collect()
+        };
+
+        for y in steps(self.repeat_y, top, view.size.h, self.size.h) {
+            for &x in &steps(self.repeat_x, left, view.size.w, self.size.w) {
+                let depth = Depth::new(layer, y + self.size.h);
+                ctx.render(RenderInfo::texture(Point::new(x, y), self.size,
+                                               Point::new(0.0, 0.0), self.size,
+                                               depth, self.tex.clone())
+                           .styled(&self.style));
+            }
         }
     }
 }
\ No newline at end of file
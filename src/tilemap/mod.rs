@@ -1,14 +1,32 @@
-use types::Size;
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use types::{Point, Size, Rect};
 use context::Context;
 
-use self::layer::{TileLayer, LayerData, ObjectLayer};
-use self::tileset::{Tileset, TilesetData, Imageset};
+use self::layer::{TileLayer, LayerData, ObjectLayer, ImageLayer, FlatLayer, flatten_layers};
+use self::tileset::{Tileset, TilesetData, Imageset, find_tileset};
+
+pub use self::collision::CollisionMap;
+pub use self::registry::MapRegistry;
+pub use self::warp::Warp;
 
 mod tileset;
 
 mod layer;
 mod tile;
+mod chunk;
 mod object;
+mod collision;
+mod encoding;
+mod tmx;
+mod property;
+mod registry;
+mod warp;
+
+pub use self::property::Properties;
+pub use self::object::MapObject;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TilemapData {
@@ -20,72 +38,424 @@ pub struct TilemapData {
     tileheight: f32,
     tilewidth: f32,
     version: u16,
+    #[serde(default)]
+    infinite: bool,
+    properties: Option<Value>,
+    propertytypes: Option<HashMap<String, String>>,
     tilesets: Vec<TilesetData>,
     layers: Vec<LayerData>
 }
 
+impl TilemapData {
+    // a Tiled json export or a .tmx file below assets/
+    pub fn load(path: &str) -> Result<TilemapData, String> {
+        if path.ends_with(".tmx") {
+            return tmx::load_tmx(path);
+        }
+
+        ::util::load_data(path)
+    }
+
+    // as (x0, y0, x1, y1), infinite maps span the chunks of all tile layers
+    pub fn cell_bounds(&self) -> (i32, i32, i32, i32) {
+        if !self.infinite {
+            return (0, 0, self.width as i32, self.height as i32);
+        }
+
+        let mut bounds: Option<(i32, i32, i32, i32)> = None;
+        for layer in self.flat_layers().iter().map(|l| l.data).filter(|l| l.is_tile_layer()) {
+            if let Some((x0, y0, x1, y1)) = layer.cell_bounds() {
+                bounds = Some(match bounds {
+                    Some((bx0, by0, bx1, by1)) => (bx0.min(x0), by0.min(y0), bx1.max(x1), by1.max(y1)),
+                    None => (x0, y0, x1, y1)
+                });
+            }
+        }
+
+        bounds.unwrap_or((0, 0, 0, 0))
+    }
+
+    pub fn properties(&self) -> Properties {
+        Properties::from_json(self.properties.as_ref(), self.propertytypes.as_ref())
+    }
+
+    pub fn flat_layers(&self) -> Vec<FlatLayer<'_>> {
+        flatten_layers(&self.layers)
+    }
+
+    // index into flat_layers(). the entitylayer property names a layer or
+    // indexes the top level layers, where a group resolves to its first child.
+    // falls back to the object layer entities, then to above the last
+    // background layer and then to above everything
+    pub fn entity_layer(&self) -> u32 {
+        let layers = self.flat_layers();
+        let properties = self.properties();
+
+        if let Some(index) = properties.get_int("entitylayer") {
+            let top = (index.max(0) as usize).min(self.layers.len());
+            return flatten_layers(&self.layers[..top]).len() as u32;
+        }
+
+        if let Some(name) = properties.get_str("entitylayer") {
+            match layers.iter().position(|l| l.data.name == name) {
+                Some(index) => return index as u32,
+                None => println!("entity layer {} not found", name)
+            }
+        }
+
+        if let Some(index) = layers.iter().position(|l| l.data.is_object_layer() && l.data.name == "entities") {
+            return index as u32;
+        }
+
+        match layers.iter().rposition(|l| l.properties.flag("background")) {
+            Some(index) => index as u32 + 1,
+            None => layers.len() as u32
+        }
+    }
+}
+
+// with its index among the flattened layers
+enum MapLayer {
+    Tiles(u32, TileLayer),
+    Objects(u32, ObjectLayer),
+    Image(u32, ImageLayer),
+}
+
 pub struct Tilemap {
     _width: u16,
     _height: u16,
-    bg_layers: Vec<TileLayer>,
-    fg_layers: Vec<TileLayer>,
-    object_layers: Vec<ObjectLayer>,
-    _tilesets: Vec<Tileset>,
+    tilesize: Size,
+    layers: Vec<MapLayer>,
+    entity_layer: u32,
+    collision: CollisionMap,
+    properties: Properties,
+    layer_properties: HashMap<String, Properties>,
+    // object id to its layer in layers and its index in that layer
+    object_index: HashMap<u32, (usize, usize)>,
+    warps: Vec<Warp>,
+    // seconds since load, drives tile animations
+    time: f32,
+    tilesets: Vec<Tileset>,
     _imagesets: Vec<Imageset>
 }
 
 impl Tilemap {
-    pub fn new(mut ctx: &mut Context) -> Tilemap {
-        use util::load_data;
-        let data: TilemapData = load_data("tilemap-small-0.json").unwrap();
+    // image layers that fail to load are skipped,
+    // undecodable tile data and missing files are errors
+    pub fn load(ctx: &mut Context, path: &str) -> Result<Tilemap, String> {
+        let data = TilemapData::load(path)?;
+        Tilemap::from_data(ctx, &data)
+    }
+
+    fn from_data(ctx: &mut Context, data: &TilemapData) -> Result<Tilemap, String> {
         let tilesize = Size::new(data.tilewidth, data.tileheight);
 
         let mut tilesets = Vec::new();
         let mut imagesets = Vec::new();
         for td in &data.tilesets {
             if td.is_image_set() {
-                imagesets.push(Imageset::new(&td, ctx));
+                imagesets.push(Imageset::new(td, ctx)?);
                 continue;
             }
 
-            tilesets.push(Tileset::new(&td, ctx));
+            tilesets.push(Tileset::new(td, ctx)?);
         }
 
-        let mut bg_layers = Vec::new();
-        let mut fg_layers = Vec::new();
-        let mut object_layers = Vec::new();
+        let mut layers = Vec::new();
+        let mut collision = CollisionMap::new(data.cell_bounds(), tilesize);
+
+        let mut layer_properties = HashMap::new();
+        let entity_layer = data.entity_layer();
+
+        for (i, flat) in data.flat_layers().iter().enumerate() {
+            let index = i as u32;
+            let tl = flat.data;
+            layer_properties.insert(tl.name.clone(), flat.properties.clone());
 
-        let mut parsing_background = true;
-        for tl in data.layers.iter() {
             if tl.is_object_layer() {
-                if tl.name == "entities" { parsing_background = false; }
-                object_layers.push(ObjectLayer::new(&tl, &imagesets));
+                collision.add_object_layer(flat, &tilesets, &imagesets);
+                layers.push(MapLayer::Objects(index, ObjectLayer::new(flat, &imagesets, &tilesets)));
                 continue;
             }
 
-            if parsing_background {
-                bg_layers.push(TileLayer::new(&tl, &tilesets, &tilesize));
+            if tl.is_image_layer() {
+                match ImageLayer::new(flat, ctx) {
+                    Ok(layer) => layers.push(MapLayer::Image(index, layer)),
+                    Err(err) => println!("could not load layer {}: {}", tl.name, err)
+                }
                 continue;
             }
 
-            fg_layers.push(TileLayer::new(&tl, &tilesets, &tilesize));
+            let blocks = match tl.decode_blocks() {
+                Ok(blocks) => blocks,
+                Err(err) => return Err(format!("could not decode layer {}: {}", tl.name, err))
+            };
+
+            collision.add_tile_layer(flat, &blocks, &tilesets);
+
+            if flat.is_collision_layer() { continue; }
+
+            let layer = TileLayer::new(flat, &blocks, &tilesets, &tilesize, index != entity_layer);
+            layer.bake(ctx);
+            layers.push(MapLayer::Tiles(index, layer));
         }
 
-        Tilemap {
+        let mut warps = Vec::new();
+        let mut object_index = HashMap::new();
+        for (i, layer) in layers.iter().enumerate() {
+            if let MapLayer::Objects(_, ref l) = *layer {
+                warps.extend(l.objects.iter().filter_map(Warp::from_object));
+                for (j, o) in l.objects.iter().enumerate() {
+                    object_index.insert(o.id, (i, j));
+                }
+            }
+        }
+
+        Ok(Tilemap {
             _width: data.width,
             _height: data.height,
-            bg_layers: bg_layers,
-            fg_layers: fg_layers,
-            object_layers: object_layers,
-            _tilesets: tilesets,
+            tilesize,
+            layers,
+            entity_layer,
+            collision,
+            properties: data.properties(),
+            layer_properties,
+            object_index,
+            warps,
+            time: 0.0,
+            tilesets,
             _imagesets: imagesets
+        })
+    }
+
+    pub fn collision(&self) -> &CollisionMap {
+        &self.collision
+    }
+
+    pub fn tile_size(&self) -> Size {
+        self.tilesize
+    }
+
+    pub fn entity_layer(&self) -> u32 {
+        self.entity_layer
+    }
+
+    pub fn properties(&self) -> &Properties {
+        &self.properties
+    }
+
+    // of all object layers, in layer order
+    pub fn objects<'a>(&'a self) -> impl Iterator<Item=&'a MapObject> + 'a {
+        self.layers.iter().flat_map(|layer| match *layer {
+            MapLayer::Objects(_, ref l) => l.objects.iter(),
+            _ => [].iter()
+        })
+    }
+
+    pub fn find_object(&self, name: &str) -> Option<&MapObject> {
+        self.objects().find(|o| o.name == name)
+    }
+
+    pub fn warp_at(&self, area: &Rect) -> Option<&Warp> {
+        self.warps.iter().find(|w| w.area.intersects(area))
+    }
+
+    // center of the named object
+    pub fn spawn_point(&self, name: &str) -> Option<Point> {
+        self.find_object(name).map(|o| o.bounds().center())
+    }
+
+    // drops layers, tilesets and objects with their textures,
+    // what is left blocks every cell
+    pub fn unload(&mut self) {
+        self.collision = CollisionMap::new((0, 0, 0, 0), self.tilesize);
+        self.properties = Properties::default();
+        self.layer_properties.clear();
+        self.layers.clear();
+        self.tilesets.clear();
+        self._imagesets.clear();
+        self.warps.clear();
+        self.object_index.clear();
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.time += dt;
+    }
+
+    pub fn draw(&self, ctx: &mut Context) {
+        for layer in &self.layers {
+            match *layer {
+                MapLayer::Tiles(index, ref l) => l.draw(index, self.time, ctx),
+                MapLayer::Objects(index, ref l) => l.draw(index, ctx),
+                MapLayer::Image(index, ref l) => l.draw(index, ctx),
+            }
+        }
+    }
+}
+
+// lookups and edits for game code, the tests are their only callers so far
+#[allow(dead_code)]
+impl Tilemap {
+    // gid 0 clears the cell, the collision map stays as it is
+    pub fn set_tile(&mut self, layer_name: &str, x: i32, y: i32, gid: u32) -> bool {
+        for layer in &mut self.layers {
+            if let MapLayer::Tiles(_, ref mut l) = *layer {
+                if l.name == layer_name {
+                    l.set_tile(x, y, gid, &self.tilesets);
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+    pub fn layer_properties(&self, name: &str) -> Option<&Properties> {
+        self.layer_properties.get(name)
+    }
+
+    pub fn object(&self, id: u32) -> Option<&MapObject> {
+        let &(layer, i) = self.object_index.get(&id)?;
+
+        match self.layers[layer] {
+            MapLayer::Objects(_, ref l) => l.objects.get(i),
+            _ => None
+        }
+    }
+
+    pub fn objects_of_type<'a>(&'a self, object_type: &'a str) -> impl Iterator<Item=&'a MapObject> + 'a {
+        self.objects().filter(move |o| o.object_type == object_type)
+    }
+
+    pub fn object_properties(&self, id: u32) -> Option<&Properties> {
+        self.object(id).map(|o| &o.properties)
+    }
+
+    pub fn tileset_properties(&self, gid: u32) -> Option<&Properties> {
+        find_tileset(&self.tilesets, gid).map(|ts| ts.properties())
+    }
+
+    pub fn tile_properties(&self, gid: u32) -> Option<&Properties> {
+        find_tileset(&self.tilesets, gid).and_then(|ts| ts.tile_properties(gid))
+    }
+
+    pub fn tile_properties_at(&self, layer_name: &str, x: i32, y: i32) -> Option<&Properties> {
+        for layer in &self.layers {
+            if let MapLayer::Tiles(_, ref l) = *layer {
+                if l.name == layer_name {
+                    return l.gid_at(x, y).and_then(|gid| self.tile_properties(gid));
+                }
+            }
         }
+
+        None
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use context::Context;
+    use types::Rect;
+    use super::{Tilemap, TilemapData};
+
+    const MAP: &str = r#"{
+        "width": 2, "height": 2, "tilewidth": 16, "tileheight": 16,
+        "nextobjectid": 6, "orientation": "orthogonal",
+        "renderorder": "right-down", "version": 1,
+        "tilesets": [{
+            "name": "tiles", "firstgid": 1, "image": "tileset.png",
+            "tilewidth": 16, "tileheight": 16, "columns": 51, "tilecount": 816,
+            "margin": 0, "spacing": 0,
+            "properties": { "biome": "forest" },
+            "tileproperties": { "1": { "solid": true } }
+        }],
+        "layers": [{
+            "type": "tilelayer", "name": "ground", "width": 2, "height": 2,
+            "opacity": 1, "visible": true, "data": [1, 2, 0, 1],
+            "properties": { "depth": 3 }
+        }, {
+            "type": "objectgroup", "name": "things", "opacity": 1, "visible": true,
+            "objects": [
+                { "id": 1, "type": "chest", "x": 0, "y": 0, "width": 16, "height": 16,
+                  "visible": true, "properties": { "gold": 5 } },
+                { "id": 2, "type": "sign", "x": 16, "y": 0, "width": 16, "height": 16,
+                  "visible": true },
+                { "id": 3, "type": "chest", "x": 0, "y": 16, "width": 16, "height": 16,
+                  "visible": true },
+                { "id": 4, "gid": 2, "x": 0, "y": 32, "width": 16, "height": 16,
+                  "visible": true },
+                { "id": 5, "type": "solid", "x": 0, "y": 0, "width": 16, "height": 16,
+                  "rotation": 30, "visible": true }
+            ]
+        }]
+    }"#;
+
+    #[test]
+    fn queries() {
+        let mut ctx = Context::builder().headless().size(32, 32).build().unwrap();
+        let data: TilemapData = serde_json::from_str(MAP).unwrap();
+        let map = Tilemap::from_data(&mut ctx, &data).unwrap();
+
+        assert_eq!(map.layer_properties("ground").and_then(|p| p.get_int("depth")), Some(3));
+        assert!(map.layer_properties("sky").is_none());
+
+        assert_eq!(map.object(2).map(|o| o.object_type.as_str()), Some("sign"));
+        assert!(map.object(9).is_none());
+        let chests: Vec<u32> = map.objects_of_type("chest").map(|o| o.id).collect();
+        assert_eq!(chests, vec![1, 3]);
+        assert_eq!(map.object_properties(1).and_then(|p| p.get_int("gold")), Some(5));
+
+        assert_eq!(map.tileset_properties(816).and_then(|p| p.get_str("biome")), Some("forest"));
+        assert!(map.tileset_properties(817).is_none());
+        // tile properties are keyed by local id, gid 2 is tile 1
+        assert!(map.tile_properties(2).unwrap().flag("solid"));
+        assert!(map.tile_properties(1).is_none());
+        assert!(map.tile_properties_at("ground", 1, 0).unwrap().flag("solid"));
+        assert!(map.tile_properties_at("ground", 1, 1).is_none());
+        assert!(map.tile_properties_at("ground", 0, 1).is_none());
+        assert!(map.tile_properties_at("things", 1, 0).is_none());
+    }
+
+    #[test]
+    fn solid_objects() {
+        let mut ctx = Context::builder().headless().size(32, 32).build().unwrap();
+        let data: TilemapData = serde_json::from_str(MAP).unwrap();
+        let map = Tilemap::from_data(&mut ctx, &data).unwrap();
+        let collision = map.collision();
+
+        // the tile object shows a solid tile, anchored at its bottom left
+        assert!(collision.is_blocked(&Rect::new(4.0, 20.0, 4.0, 4.0)));
+        // rotated shapes are skipped
+        assert!(!collision.is_blocked(&Rect::new(4.0, 4.0, 4.0, 4.0)));
+    }
+
+    #[test]
+    fn undecodable_layer() {
+        let mut ctx = Context::builder().headless().size(32, 32).build().unwrap();
+        let broken = MAP.replace(r#""data": [1, 2, 0, 1]"#, r#""encoding": "base32", "data": "AAAA""#);
+        let data: TilemapData = serde_json::from_str(&broken).unwrap();
+
+        let err = Tilemap::from_data(&mut ctx, &data).err().unwrap();
+        assert!(err.contains("ground"), "{}", err);
+    }
+
+    #[test]
+    fn set_tile() {
+        let mut ctx = Context::builder().headless().size(32, 32).build().unwrap();
+        let data: TilemapData = serde_json::from_str(MAP).unwrap();
+        let mut map = Tilemap::from_data(&mut ctx, &data).unwrap();
+        let solid = |map: &Tilemap, x, y| map.tile_properties_at("ground", x, y).is_some_and(|p| p.flag("solid"));
 
-    pub fn draw(&self, mut ctx: &mut Context) {
-        //TODO calculate z values according to map size
-        for layer in &self.bg_layers { layer.draw(-10000.0, ctx); }
-        for layer in &self.fg_layers { layer.draw(10000.0, ctx); }
-        for object_layer in &self.object_layers { object_layer.draw(ctx); }
+        // grows the chunk, the other tiles stay in their cells
+        assert!(map.set_tile("ground", 5, 5, 2));
+        assert!(solid(&map, 5, 5) && solid(&map, 1, 0));
+        assert!(map.set_tile("ground", 1, 0, 0));
+        assert!(!solid(&map, 1, 0));
+        // starts a new chunk
+        assert!(map.set_tile("ground", -40, 3, 2));
+        assert!(solid(&map, -40, 3));
+        assert!(!map.set_tile("things", 0, 0, 2));
     }
 }
@@ -1,60 +1,310 @@
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::collections::HashMap;
 
-use types::{Point, Size, RenderInfo, Texture};
+use serde_json::Value;
+
+use types::{Point, Size, Rect, Depth, RenderInfo, RenderStyle, Texture, Transform, Color};
 use context::Context;
 
-use super::tileset::Imageset;
+use super::tileset::{Imageset, Tileset, find_tileset};
+use super::tile::TileFlags;
+use super::property::{Properties, parse_color};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ObjectData {
-    width: u16,
-    height: u16,
+    width: f32,
+    height: f32,
     x: f32,
     y: f32,
     visible: bool,
-    id: u16,
-    gid: u32
+    id: u32,
+    #[serde(default)]
+    name: String,
+    // degrees clockwise around the top left corner
+    #[serde(default)]
+    rotation: f32,
+
+    // only tile objects carry a gid
+    #[serde(default)]
+    gid: u32,
+    #[serde(rename="type", default)]
+    object_type: String,
+    // newer exports call the type class
+    #[serde(default)]
+    class: String,
+    properties: Option<Value>,
+    propertytypes: Option<HashMap<String, String>>,
+
+    // shape, a rectangle if none of these are set
+    #[serde(default)]
+    point: bool,
+    #[serde(default)]
+    ellipse: bool,
+    polygon: Option<Vec<PointData>>,
+    polyline: Option<Vec<PointData>>,
+    text: Option<TextData>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PointData {
+    x: f32,
+    y: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TextData {
+    text: String,
+    #[serde(default)]
+    wrap: bool,
+    color: Option<String>,
+    fontfamily: Option<String>,
+    pixelsize: Option<u32>,
+    halign: Option<String>,
+    valign: Option<String>,
+    #[serde(default)]
+    bold: bool,
+    #[serde(default)]
+    italic: bool,
+}
+
+impl ObjectData {
+    pub fn is_rect(&self) -> bool {
+        match self.kind() {
+            ObjectKind::Rect => self.width > 0.0 && self.height > 0.0,
+            _ => false
+        }
+    }
+
+    // type solid or a solid property set to true
+    pub fn is_solid(&self) -> bool {
+        self.object_type == "solid" || self.properties().flag("solid")
+    }
+
+    pub fn object_type(&self) -> &str {
+        if self.object_type.is_empty() { &self.class } else { &self.object_type }
+    }
+
+    pub fn kind(&self) -> ObjectKind {
+        let points = |list: &Vec<PointData>| list.iter().map(|p| Point::new(p.x, p.y)).collect();
+
+        let (gid, _) = TileFlags::split_gid(self.gid);
+        if gid != 0 { return ObjectKind::Tile(gid); }
+
+        if let Some(ref list) = self.polygon { return ObjectKind::Polygon(points(list)); }
+        if let Some(ref list) = self.polyline { return ObjectKind::Polyline(points(list)); }
+        if let Some(ref text) = self.text { return ObjectKind::Text(TextObject::new(text)); }
+
+        if self.point { ObjectKind::Point }
+        else if self.ellipse { ObjectKind::Ellipse }
+        else { ObjectKind::Rect }
+    }
+
+    pub fn properties(&self) -> Properties {
+        Properties::from_json(self.properties.as_ref(), self.propertytypes.as_ref())
+    }
+}
+
+// nothing draws text yet, game code can read it off the object
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
+pub struct TextObject {
+    pub text: String,
+    pub wrap: bool,
+    pub color: Color,
+    pub font_family: String,
+    pub pixel_size: u32,
+    // left, center, right or justify
+    pub halign: String,
+    // top, center or bottom
+    pub valign: String,
+    pub bold: bool,
+    pub italic: bool,
+}
+
+impl TextObject {
+    fn new(data: &TextData) -> TextObject {
+        TextObject {
+            text: data.text.clone(),
+            wrap: data.wrap,
+            color: data.color.as_ref()
+                .and_then(|c| parse_color(c))
+                .unwrap_or(Color::RGB(0, 0, 0)),
+            font_family: data.fontfamily.clone().unwrap_or(String::from("sans-serif")),
+            pixel_size: data.pixelsize.unwrap_or(16),
+            halign: data.halign.clone().unwrap_or(String::from("left")),
+            valign: data.valign.clone().unwrap_or(String::from("top")),
+            bold: data.bold,
+            italic: data.italic
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ObjectKind {
+    Rect,
+    Point,
+    Ellipse,
+    // points are relative to the object's position
+    Polygon(Vec<Point>),
+    Polyline(Vec<Point>),
+    #[allow(dead_code)]
+    Text(TextObject),
+    // gid without flip flags
+    Tile(u32),
+}
+
+// any object of an object layer, hidden ones included
+#[derive(Clone, Debug)]
+pub struct MapObject {
+    pub id: u32,
+    pub name: String,
+    pub object_type: String,
+    pub pos: Point,
+    pub size: Size,
+    pub rotation: f32,
+    pub visible: bool,
+    pub kind: ObjectKind,
+    pub properties: Properties,
+}
+
+impl MapObject {
+    pub fn new(data: &ObjectData) -> MapObject {
+        MapObject {
+            id: data.id,
+            name: data.name.clone(),
+            object_type: String::from(data.object_type()),
+            pos: Point::new(data.x, data.y),
+            size: Size::new(data.width, data.height),
+            rotation: data.rotation,
+            visible: data.visible,
+            kind: data.kind(),
+            properties: data.properties()
+        }
+    }
+
+    // unrotated, tile objects are anchored at their bottom left corner
+    pub fn bounds(&self) -> Rect {
+        match self.kind {
+            ObjectKind::Tile(_) => Rect::new(self.pos.x, self.pos.y - self.size.h,
+                                             self.size.w, self.size.h),
+            ObjectKind::Polygon(ref points) | ObjectKind::Polyline(ref points) => {
+                let (mut x0, mut y0, mut x1, mut y1) = (0.0f32, 0.0f32, 0.0f32, 0.0f32);
+                for p in points {
+                    x0 = x0.min(p.x); y0 = y0.min(p.y);
+                    x1 = x1.max(p.x); y1 = y1.max(p.y);
+                }
+                Rect::new(self.pos.x + x0, self.pos.y + y0, x1 - x0, y1 - y0)
+            },
+            _ => Rect::new(self.pos.x, self.pos.y, self.size.w, self.size.h)
+        }
+    }
 }
 
 pub struct TileObject {
+    // tiled's anchor, the bottom left corner before rotation
+    anchor: Point,
     pos: Point,
     size: Size,
     src: Point,
     src_size: Size,
+    transform: Transform,
     tex: Rc<RefCell<Texture>>
 }
 
 impl TileObject {
-    pub fn new(data: &ObjectData, imagesets: &Vec<Imageset>) -> Option<TileObject> {
-        let gid = data.gid;
+    // None if no tileset or image collection has the gid
+    pub fn new(data: &ObjectData, imagesets: &[Imageset], tilesets: &[Tileset]) -> Option<TileObject> {
+        let (gid, flags) = TileFlags::split_gid(data.gid);
         if gid == 0 { return None; }
-        let mut imageset_id = 0;
 
-        for (i, is) in imagesets.iter().enumerate() {
-            if gid >= is.firstgid && gid < is.firstgid + is.tilecount {
-                imageset_id = i;
+        let (tex, src, src_size) = match find_tileset(tilesets, gid) {
+            Some(ts) => {
+                let src = ts.geometry.src_pos(gid - ts.firstgid)?;
+                (ts.get_tex(), src, ts.geometry.tilesize)
+            },
+            None => {
+                let tex = imagesets.iter().rev()
+                    .find(|is| is.contains(gid))
+                    .and_then(|is| is.get_tex_for_gid(gid))?;
+                let query = tex.borrow().query();
+                (tex, Point::new(0.0, 0.0), Size::new(query.width as f32, query.height as f32))
             }
-        }
+        };
 
-        let tex = imagesets[imageset_id].get_tex_for_gid(gid);
-        if !tex.is_some() { return None; }
-        let tex = tex.unwrap();
-        let tex_info = tex.borrow().query();
+        // tiled rotates around the anchor, the renderer around the center,
+        // so move the center to where rotating around the anchor puts it
+        let (w, h) = (data.width, data.height);
+        let (sin, cos) = (data.rotation as f64).to_radians().sin_cos();
+        let (sin, cos) = (sin as f32, cos as f32);
+        let center = Point::new(data.x + w / 2.0 * cos + h / 2.0 * sin,
+                                data.y + w / 2.0 * sin - h / 2.0 * cos);
+
+        let mut transform = flags.to_transform();
+        transform.angle += data.rotation as f64;
 
         Some(TileObject { 
-            pos: Point::new(data.x, data.y - data.height as f32),
-            size: Size::new(data.width as f32, data.height as f32),
-            src: Point::new(0.0, 0.0),
-            src_size: Size::new(tex_info.width as f32, tex_info.height as f32),
-            tex: tex.clone()
+            anchor: Point::new(data.x, data.y),
+            pos: Point::new(center.x - w / 2.0, center.y - h / 2.0),
+            size: Size::new(w, h),
+            src,
+            src_size,
+            transform,
+            tex
         })
     }
 
-    pub fn draw(&self, ctx: &mut Context) {
-        let z = self.pos.y + self.size.h / 2.0;
+    // for topdown draw order
+    pub fn sort_y(&self) -> f32 {
+        self.anchor.y
+    }
+
+    pub fn draw(&self, depth: Depth, style: &RenderStyle, ctx: &mut Context) {
         ctx.render(RenderInfo::texture(self.pos, self.size,
                                        self.src, self.src_size,
-                                       z, self.tex.clone()));
+                                       depth, self.tex.clone())
+                   .transformed(self.transform)
+                   .styled(style));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use context::Context;
+    use super::super::tileset::{Tileset, TilesetData, Imageset};
+    use super::{ObjectData, TileObject};
+
+    fn tile_object(gid: u32) -> ObjectData {
+        serde_json::from_str(&format!(r#"{{
+            "id": 1, "gid": {}, "x": 0, "y": 16, "width": 16, "height": 16, "visible": true
+        }}"#, gid)).unwrap()
+    }
+
+    #[test]
+    fn tile_object_gids() {
+        let mut ctx = Context::builder().headless().size(16, 16).build().unwrap();
+        let atlas: TilesetData = serde_json::from_str(r#"{
+            "name": "tiles", "firstgid": 1, "image": "tileset.png",
+            "tilewidth": 16, "tileheight": 16, "columns": 16, "tilecount": 816,
+            "imagewidth": 256, "imageheight": 816, "margin": 0, "spacing": 0
+        }"#).unwrap();
+        let images: TilesetData = serde_json::from_str(r#"{
+            "name": "images", "firstgid": 900, "tilewidth": 16, "tileheight": 16,
+            "columns": 0, "tilecount": 2, "margin": 0, "spacing": 0
+        }"#).unwrap();
+        let tilesets = vec![Tileset::new(&atlas, &mut ctx).unwrap()];
+        let imagesets = vec![Imageset::new(&images, &mut ctx).unwrap()];
+
+        // atlas tiles are cut out of the tileset image
+        let o = TileObject::new(&tile_object(18), &imagesets, &tilesets).unwrap();
+        assert_eq!((o.src.x, o.src.y), (16.0, 16.0));
+        assert_eq!((o.src_size.w, o.src_size.h), (16.0, 16.0));
+
+        // gids without a tileset or an image are skipped
+        assert!(TileObject::new(&tile_object(850), &imagesets, &tilesets).is_none());
+        assert!(TileObject::new(&tile_object(900), &imagesets, &tilesets).is_none());
+        assert!(TileObject::new(&tile_object(5), &imagesets, &[]).is_none());
     }
 }
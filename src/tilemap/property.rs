@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use types::Color;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Property {
+    String(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Color(Color),
+    // path relative to the map file
+    File(String),
+    // id of an object on the same map, 0 if unset
+    Object(u32),
+}
+
+impl Property {
+    // without a type it is guessed from the value
+    pub fn from_json(value: &Value, prop_type: Option<&str>) -> Option<Property> {
+        match (prop_type, value) {
+            (Some("string"), Value::String(s)) => Some(Property::String(s.clone())),
+            (Some("file"), Value::String(s)) => Some(Property::File(s.clone())),
+            (Some("color"), Value::String(s)) => parse_color(s).map(Property::Color),
+            (Some("object"), v) => v.as_u64().map(|id| Property::Object(id as u32)),
+            (Some("int"), v) => v.as_i64().or(v.as_f64().map(|f| f as i64)).map(Property::Int),
+            (Some("float"), v) => v.as_f64().map(Property::Float),
+            (Some("bool"), &Value::Bool(b)) => Some(Property::Bool(b)),
+            (_, Value::String(s)) => Some(Property::String(s.clone())),
+            (_, &Value::Bool(b)) => Some(Property::Bool(b)),
+            (_, v) if v.is_i64() || v.is_u64() => v.as_i64().map(Property::Int),
+            (_, v) => v.as_f64().map(Property::Float)
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Properties {
+    values: HashMap<String, Property>,
+}
+
+impl Properties {
+    // both export formats, a name to value map with separate
+    // propertytypes or a list of { name, type, value } entries
+    pub fn from_json(properties: Option<&Value>, types: Option<&HashMap<String, String>>) -> Properties {
+        let mut values = HashMap::new();
+
+        match properties {
+            Some(Value::Object(map)) => {
+                for (name, v) in map {
+                    let prop_type = types.and_then(|t| t.get(name)).map(|t| t.as_str());
+                    if let Some(p) = Property::from_json(v, prop_type) {
+                        values.insert(name.clone(), p);
+                    }
+                }
+            },
+            Some(Value::Array(list)) => {
+                for entry in list {
+                    let name = match entry.get("name").and_then(|n| n.as_str()) {
+                        Some(name) => name,
+                        None => continue
+                    };
+
+                    let prop_type = entry.get("type").and_then(|t| t.as_str());
+                    let p = entry.get("value").and_then(|v| Property::from_json(v, prop_type));
+                    if let Some(p) = p {
+                        values.insert(String::from(name), p);
+                    }
+                }
+            },
+            _ => {}
+        }
+
+        Properties { values }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Property> {
+        self.values.get(name)
+    }
+
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        match self.get(name) {
+            Some(&Property::Bool(b)) => Some(b),
+            _ => None
+        }
+    }
+
+    // false unless set to true
+    pub fn flag(&self, name: &str) -> bool {
+        self.get_bool(name).unwrap_or(false)
+    }
+
+    pub fn get_int(&self, name: &str) -> Option<i64> {
+        match self.get(name) {
+            Some(&Property::Int(i)) => Some(i),
+            _ => None
+        }
+    }
+
+    // string and file properties
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        match self.get(name) {
+            Some(&Property::String(ref s)) | Some(&Property::File(ref s)) => Some(s),
+            _ => None
+        }
+    }
+}
+
+// the map code only reads ints, strings and flags so far
+#[allow(dead_code)]
+impl Properties {
+    // ints are converted
+    pub fn get_float(&self, name: &str) -> Option<f64> {
+        match self.get(name) {
+            Some(&Property::Float(f)) => Some(f),
+            Some(&Property::Int(i)) => Some(i as f64),
+            _ => None
+        }
+    }
+
+    pub fn get_color(&self, name: &str) -> Option<Color> {
+        match self.get(name) {
+            Some(&Property::Color(c)) => Some(c),
+            _ => None
+        }
+    }
+
+    // id of the referenced object, None if unset
+    pub fn get_object(&self, name: &str) -> Option<u32> {
+        match self.get(name) {
+            Some(&Property::Object(id)) if id != 0 => Some(id),
+            _ => None
+        }
+    }
+}
+
+// #AARRGGBB or #RRGGBB
+pub fn parse_color(s: &str) -> Option<Color> {
+    let hex = s.trim_start_matches('#');
+    let v = match u32::from_str_radix(hex, 16) {
+        Ok(v) => v,
+        Err(_) => return None
+    };
+
+    let channel = |shift: u32| ((v >> shift) & 0xff) as u8;
+    match hex.len() {
+        6 => Some(Color::RGBA(channel(16), channel(8), channel(0), 0xff)),
+        8 => Some(Color::RGBA(channel(16), channel(8), channel(0), channel(24))),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+    use types::Color;
+    use super::{Properties, Property};
+
+    #[test]
+    fn map_with_types() {
+        let values = serde_json::from_str(r##"{
+            "entitylayer": 4, "speed": 1, "tint": "#80ff0000",
+            "script": "warp.lua", "target": 7, "solid": true
+        }"##).unwrap();
+        let types = serde_json::from_str(r#"{
+            "entitylayer": "int", "speed": "float", "tint": "color",
+            "script": "file", "target": "object", "solid": "bool"
+        }"#).unwrap();
+
+        let p = Properties::from_json(Some(&values), Some(&types));
+        assert_eq!(p.get_int("entitylayer"), Some(4));
+        assert_eq!(p.get("speed"), Some(&Property::Float(1.0)));
+        assert_eq!(p.get_float("entitylayer"), Some(4.0));
+        assert_eq!(p.get_color("tint"), Some(Color::RGBA(0xff, 0, 0, 0x80)));
+        assert_eq!(p.get_str("script"), Some("warp.lua"));
+        assert_eq!(p.get_object("target"), Some(7));
+        assert!(p.flag("solid"));
+        assert!(!p.flag("missing"));
+    }
+
+    #[test]
+    fn property_list() {
+        let list = serde_json::from_str(r##"[
+            { "name": "damage", "type": "int", "value": 3 },
+            { "name": "spawn", "type": "string", "value": "slime" },
+            { "name": "color", "type": "color", "value": "#00ff00" }
+        ]"##).unwrap();
+
+        let p = Properties::from_json(Some(&list), None);
+        assert_eq!(p.get_int("damage"), Some(3));
+        assert_eq!(p.get_str("spawn"), Some("slime"));
+        assert_eq!(p.get_color("color"), Some(Color::RGBA(0, 0xff, 0, 0xff)));
+    }
+}
@@ -0,0 +1,40 @@
+use std::collections::HashMap;
+
+use context::Context;
+
+use super::Tilemap;
+
+// maps loadable by name, read from assets/maps.json
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MapRegistry {
+    // name of the map a new game starts on
+    start: String,
+    // map name to path below assets/
+    maps: HashMap<String, String>,
+}
+
+impl MapRegistry {
+    pub fn load(path: &str) -> Result<MapRegistry, String> {
+        let registry: MapRegistry = ::util::load_data(path)?;
+        if !registry.maps.contains_key(&registry.start) {
+            return Err(format!("start map {} is not registered", registry.start));
+        }
+
+        Ok(registry)
+    }
+
+    pub fn start(&self) -> &str {
+        &self.start
+    }
+
+    pub fn path(&self, name: &str) -> Option<&str> {
+        self.maps.get(name).map(|p| p.as_str())
+    }
+
+    pub fn load_map(&self, ctx: &mut Context, name: &str) -> Result<Tilemap, String> {
+        match self.path(name) {
+            Some(path) => Tilemap::load(ctx, path),
+            None => Err(format!("unknown map: {}", name))
+        }
+    }
+}
@@ -1,62 +1,171 @@
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::rc::Rc;
 
-use types::{Point, Size, RenderInfo, Texture};
-use context::Context;
+use serde_json::Value;
 
-use super::tileset::Tileset;
+use types::{Point, Size, Depth, RenderInfo, Texture, Transform};
+
+use animation::Animation;
+
+use super::tileset::{Tileset, TileGeometry, find_tileset};
+
+const FLIPPED_HORIZONTALLY: u32 = 0x80000000;
+const FLIPPED_VERTICALLY: u32 = 0x40000000;
+const FLIPPED_DIAGONALLY: u32 = 0x20000000;
+// only used by hexagonal maps, masked out but otherwise ignored
+const ROTATED_HEXAGONAL: u32 = 0x10000000;
+const FLAG_MASK: u32 = FLIPPED_HORIZONTALLY | FLIPPED_VERTICALLY |
+                       FLIPPED_DIAGONALLY | ROTATED_HEXAGONAL;
+
+// flip flags Tiled keeps in the top bits of a gid
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct TileFlags {
+    pub horizontal: bool,
+    pub vertical: bool,
+    pub diagonal: bool,
+}
+
+impl TileFlags {
+    pub fn split_gid(raw: u32) -> (u32, TileFlags) {
+        let flags = TileFlags {
+            horizontal: raw & FLIPPED_HORIZONTALLY != 0,
+            vertical: raw & FLIPPED_VERTICALLY != 0,
+            diagonal: raw & FLIPPED_DIAGONALLY != 0,
+        };
+
+        (raw & !FLAG_MASK, flags)
+    }
+
+    // Tiled flips diagonally first, SDL rotates after flipping, so a
+    // diagonal flip becomes a 90 degree turn with swapped flips
+    pub fn to_transform(self) -> Transform {
+        if !self.diagonal {
+            return Transform {
+                angle: 0.0,
+                flip_h: self.horizontal,
+                flip_v: self.vertical
+            };
+        }
+
+        Transform {
+            angle: 90.0,
+            flip_h: self.vertical,
+            flip_v: !self.horizontal
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TileData {
-    pub image: String
+    pub image: Option<String>,
+    pub animation: Option<Vec<TileFrameData>>,
+    pub properties: Option<Value>,
+    pub propertytypes: Option<HashMap<String, String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TileFrameData {
+    pub tileid: u32,
+    // milliseconds
+    pub duration: u32,
 }
 
 pub struct Tile {
+    // without flip flags
+    pub gid: u32,
     pos: Point,
     size: Size,
     src: Point,
     src_size: Size,
+    transform: Transform,
     tex: Rc<RefCell<Texture>>,
+    // frames index local tile ids of the tileset
+    animation: Option<(Rc<Animation>, TileGeometry)>,
 }
 
 impl Tile {
     pub fn from_gid(gid: u32, tilesets: &Vec<Tileset>, pos: Point, size: Size) -> Option<Tile> {
+        let (gid, flags) = TileFlags::split_gid(gid);
         if gid == 0 { return None; }
-        let mut tileset_id = 0;
+        let tileset = find_tileset(tilesets, gid)?;
 
-        for (i, ts) in tilesets.iter().enumerate() {
-            if gid >= ts.firstgid && gid < ts.firstgid + ts.tilecount {
-                tileset_id = i;
-            }
-        }
-
-        let tileset = &tilesets[tileset_id];
-        let gid = gid - tileset.firstgid;
-        let (mut x, mut y) = (0.0, 0.0); 
-        if gid != 0 {
-            let size = tileset.tilesize;
-            let cols = tileset.columns;
-
-            let offset = gid % cols;
-            x = offset as f32 * size.w;
-            let row: u32 = gid / cols;
-            y = row as f32 * size.h;
-        }
-
-        let src = Point::new(x, y);
+        let id = gid - tileset.firstgid;
+        let src = tileset.geometry.src_pos(id)?;
 
         Some(Tile {
+            gid,
             pos: pos,
             size: size,
             src: src,
-            src_size: tileset.tilesize,
+            src_size: tileset.geometry.tilesize,
+            transform: flags.to_transform(),
             tex: tileset.get_tex(),
+            animation: tileset.get_animation(id).map(|a| (a, tileset.geometry)),
         })
     }
 
-    pub fn draw(&self, z: f32, ctx: &mut Context) {
-        ctx.render(RenderInfo::texture(self.pos, self.size,
-                                       self.src, self.src_size,
-                                       z, self.tex.clone()));
+    // all instances of an animated tile share the map clock
+    fn src_at(&self, time: f32) -> Point {
+        if let Some((ref anim, ref geometry)) = self.animation {
+            if let Some(src) = anim.frame_at(time).and_then(|f| geometry.src_pos(f.index as u32)) {
+                return src;
+            }
+        }
+
+        self.src
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.animation.is_some()
+    }
+
+    pub fn render_info(&self, layer: u32, time: f32) -> RenderInfo {
+        let depth = Depth::new(layer, self.pos.y + self.size.h);
+        RenderInfo::texture(self.pos, self.size,
+                            self.src_at(time), self.src_size,
+                            depth, self.tex.clone())
+            .transformed(self.transform)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use types::Transform;
+    use super::TileFlags;
+
+    // offsets from the tile center, y pointing down
+
+    fn tiled(f: &TileFlags, (mut x, mut y): (i32, i32)) -> (i32, i32) {
+        if f.diagonal { ::std::mem::swap(&mut x, &mut y); }
+        if f.horizontal { x = -x; }
+        if f.vertical { y = -y; }
+        (x, y)
+    }
+
+    fn sdl(t: &Transform, (mut x, mut y): (i32, i32)) -> (i32, i32) {
+        if t.flip_h { x = -x; }
+        if t.flip_v { y = -y; }
+        match t.angle as i32 {
+            0 => (x, y),
+            // clockwise
+            90 => (-y, x),
+            angle => panic!("unexpected angle {}", angle)
+        }
+    }
+
+    #[test]
+    fn every_flip_combination() {
+        for bits in 0..8 {
+            let f = TileFlags {
+                horizontal: bits & 1 != 0,
+                vertical: bits & 2 != 0,
+                diagonal: bits & 4 != 0
+            };
+            let t = f.to_transform();
+            for &p in &[(1, 0), (0, 1), (2, 3)] {
+                assert_eq!(sdl(&t, p), tiled(&f, p), "{:?} at {:?}", f, p);
+            }
+        }
     }
 }
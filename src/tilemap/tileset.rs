@@ -2,10 +2,15 @@ use std::collections::HashMap;
 use std::cell::RefCell;
 use std::rc::Rc;
 
+use serde_json::Value;
+
 use context::Context;
-use types::{Size, Texture};
+use types::{Point, Size, Texture};
+
+use animation::{Animation, AnimFrame};
 
 use super::tile::TileData;
+use super::property::Properties;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TilesetData {
@@ -23,66 +28,267 @@ pub struct TilesetData {
     imageheight: Option<u16>,
     imagewidth: Option<u16>,
 
-    //TODO handle image set loading
-    // image collection tileset
+    // per-tile images for image collection tilesets
+    // and animations, keyed by local tile id
     tiles: Option<HashMap<String, TileData>>,
+
+    properties: Option<Value>,
+    propertytypes: Option<HashMap<String, String>>,
+
+    // per-tile properties keyed by local tile id
+    tileproperties: Option<HashMap<String, Value>>,
+    tilepropertytypes: Option<HashMap<String, HashMap<String, String>>>,
 }
 
 impl TilesetData {
     pub fn is_image_set(&self) -> bool {
-        self.tiles.is_some()
+        self.image.is_none()
+    }
+
+    // keyed by local tile id
+    fn tile_properties(&self) -> HashMap<u32, Properties> {
+        let mut tile_properties = HashMap::new();
+        if let Some(ref props) = self.tileproperties {
+            for (id, p) in props {
+                let types = self.tilepropertytypes.as_ref().and_then(|t| t.get(id));
+                if let Ok(id) = id.parse::<u32>() {
+                    tile_properties.insert(id, Properties::from_json(Some(p), types));
+                }
+            }
+        }
+
+        // newer exports keep tile properties next to the tile's other data
+        if let Some(ref tiles) = self.tiles {
+            for (id, tile) in tiles.iter().filter(|&(_, t)| t.properties.is_some()) {
+                if let Ok(id) = id.parse::<u32>() {
+                    tile_properties.insert(id, Properties::from_json(tile.properties.as_ref(),
+                                                                     tile.propertytypes.as_ref()));
+                }
+            }
+        }
+
+        tile_properties
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct TileGeometry {
+    pub tilesize: Size,
+    pub margin: f32,
+    pub spacing: f32,
+    pub columns: u32,
+    // None if the image size is unknown
+    pub rows: Option<u32>,
+}
+
+impl TileGeometry {
+    pub fn new(tilesize: Size, margin: u16, spacing: u16,
+               columns: u32, image: Option<Size>) -> TileGeometry {
+        let (margin, spacing) = (margin as f32, spacing as f32);
+        let fit = |extent: f32, tile: f32| {
+            ((extent - 2.0 * margin + spacing) / (tile + spacing)).floor().max(0.0) as u32
+        };
+
+        let columns = match (columns, image) {
+            (0, Some(image)) => fit(image.w, tilesize.w),
+            _ => columns
+        };
+
+        TileGeometry {
+            tilesize,
+            margin,
+            spacing,
+            columns,
+            rows: image.map(|image| fit(image.h, tilesize.h))
+        }
+    }
+
+    // by local tile id
+    pub fn src_pos(&self, id: u32) -> Option<Point> {
+        if self.columns == 0 { return None; }
+
+        let (col, row) = (id % self.columns, id / self.columns);
+        if let Some(rows) = self.rows {
+            if row >= rows { return None; }
+        }
+
+        Some(Point::new(self.margin + col as f32 * (self.tilesize.w + self.spacing),
+                        self.margin + row as f32 * (self.tilesize.h + self.spacing)))
     }
 }
 
 pub struct Tileset {
     pub firstgid: u32,
     pub tilecount: u32,
-    pub tilesize: Size,
-    pub columns: u32,
+    pub geometry: TileGeometry,
     tex: Rc<RefCell<Texture>>,
+    properties: Properties,
+    tile_properties: HashMap<u32, Properties>,
+    animations: HashMap<u32, Rc<Animation>>,
 }
 
 impl Tileset {
-    pub fn new(data: &TilesetData, ctx: &mut Context) -> Tileset {
-        Tileset {
+    pub fn new(data: &TilesetData, ctx: &mut Context) -> Result<Tileset, String> {
+        let mut animations = HashMap::new();
+        if let Some(ref tiles) = data.tiles {
+            for (id, tile) in tiles {
+                let frames = match tile.animation {
+                    Some(ref frames) => frames,
+                    None => continue
+                };
+
+                let mut anim = Animation::new(id);
+                for f in frames {
+                    anim.frames.push(AnimFrame::new(f.tileid as usize, f.duration as f32 / 1000.0));
+                }
+
+                if let Ok(id) = id.parse::<u32>() {
+                    animations.insert(id, Rc::new(anim));
+                }
+            }
+        }
+
+        let tilesize = Size::new(data.tilewidth as f32, data.tileheight as f32);
+        let image_size = match (data.imagewidth, data.imageheight) {
+            (Some(w), Some(h)) => Some(Size::new(w as f32, h as f32)),
+            _ => None
+        };
+
+        Ok(Tileset {
             firstgid: data.firstgid,
             tilecount: data.tilecount,
-            tilesize: Size::new(data.tilewidth as f32, data.tileheight as f32),
-            columns: data.columns,
-            tex: ctx.load_texture(data.image.as_ref().unwrap())
-        }
+            geometry: TileGeometry::new(tilesize, data.margin, data.spacing,
+                                        data.columns, image_size),
+            tex: ctx.load_texture(data.image.as_ref().unwrap())?,
+            properties: Properties::from_json(data.properties.as_ref(), data.propertytypes.as_ref()),
+            tile_properties: data.tile_properties(),
+            animations
+        })
     }
     
     pub fn get_tex(&self) -> Rc<RefCell<Texture>> {
         self.tex.clone()
     }
+
+    pub fn get_animation(&self, id: u32) -> Option<Rc<Animation>> {
+        self.animations.get(&id).cloned()
+    }
+
+    pub fn contains(&self, gid: u32) -> bool {
+        gid >= self.firstgid && gid < self.firstgid + self.tilecount
+    }
+
+    pub fn properties(&self) -> &Properties {
+        &self.properties
+    }
+
+    pub fn tile_properties(&self, gid: u32) -> Option<&Properties> {
+        self.tile_properties.get(&(gid - self.firstgid))
+    }
+
+    pub fn tile_flag(&self, gid: u32, name: &str) -> bool {
+        self.tile_properties(gid).is_some_and(|p| p.flag(name))
+    }
+}
+
+pub fn find_tileset(tilesets: &[Tileset], gid: u32) -> Option<&Tileset> {
+    tilesets.iter().rev().find(|ts| ts.contains(gid))
 }
 
 pub struct Imageset {
     pub firstgid: u32,
     pub tilecount: u32,
     images: HashMap<String, Rc<RefCell<Texture>>>,
+    tile_properties: HashMap<u32, Properties>,
 }
 
 impl Imageset {
-    pub fn new(data: &TilesetData, ctx: &mut Context) -> Imageset {
+    pub fn new(data: &TilesetData, ctx: &mut Context) -> Result<Imageset, String> {
         let mut images = HashMap::new();
-        for (name, tile_data) in data.tiles.as_ref().unwrap() {
-            images.insert(name.to_owned(), ctx.load_texture(&tile_data.image));
+        if let Some(ref tiles) = data.tiles {
+            for (name, tile_data) in tiles {
+                if let Some(ref image) = tile_data.image {
+                    images.insert(name.to_owned(), ctx.load_texture(image)?);
+                }
+            }
         }
 
-        Imageset {
+        Ok(Imageset {
             firstgid: data.firstgid,
             tilecount: data.tilecount,
-            images: images
-        }
+            images,
+            tile_properties: data.tile_properties()
+        })
+    }
+
+    pub fn contains(&self, gid: u32) -> bool {
+        gid >= self.firstgid && gid < self.firstgid + self.tilecount
     }
 
     pub fn get_tex_for_gid(&self, gid: u32) -> Option<Rc<RefCell<Texture>>> {
+        if !self.contains(gid) { return None; }
         let gid = gid - self.firstgid;
         match self.images.get(&gid.to_string()) {
             Some(image) => Some(image.clone()),
             None => None
         }
     }
+
+    pub fn tile_flag(&self, gid: u32, name: &str) -> bool {
+        self.contains(gid) && self.tile_properties.get(&(gid - self.firstgid)).is_some_and(|p| p.flag(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use types::{Point, Size};
+    use super::TileGeometry;
+
+    fn assert_pos(p: Option<Point>, x: f32, y: f32) {
+        let p = p.expect("tile should be inside the image");
+        assert_eq!((p.x, p.y), (x, y));
+    }
+
+    #[test]
+    fn packed_tileset() {
+        // tileset.png: 16x16 tiles, 256x816 image
+        let g = TileGeometry::new(Size::new(16.0, 16.0), 0, 0, 16,
+                                  Some(Size::new(256.0, 816.0)));
+
+        assert_pos(g.src_pos(0), 0.0, 0.0);
+        assert_pos(g.src_pos(1), 16.0, 0.0);
+        assert_pos(g.src_pos(17), 16.0, 16.0);
+        assert_pos(g.src_pos(815), 240.0, 800.0);
+        assert!(g.src_pos(816).is_none());
+    }
+
+    #[test]
+    fn margin_and_spacing() {
+        // extruded 16x16 tiles with a 1px margin and 2px spacing
+        let g = TileGeometry::new(Size::new(16.0, 16.0), 1, 2, 0,
+                                  Some(Size::new(128.0, 74.0)));
+
+        assert_eq!(g.columns, 7);
+        assert_eq!(g.rows, Some(4));
+        assert_pos(g.src_pos(0), 1.0, 1.0);
+        assert_pos(g.src_pos(1), 19.0, 1.0);
+        assert_pos(g.src_pos(7), 1.0, 19.0);
+        assert_pos(g.src_pos(27), 109.0, 55.0);
+        assert!(g.src_pos(28).is_none());
+    }
+
+    #[test]
+    fn declared_columns_win() {
+        let g = TileGeometry::new(Size::new(32.0, 32.0), 0, 0, 4, None);
+
+        assert_eq!(g.rows, None);
+        assert_pos(g.src_pos(5), 32.0, 32.0);
+        assert_pos(g.src_pos(400), 0.0, 3200.0);
+    }
+
+    #[test]
+    fn no_columns() {
+        let g = TileGeometry::new(Size::new(32.0, 32.0), 0, 0, 0, None);
+        assert!(g.src_pos(0).is_none());
+    }
 }
@@ -0,0 +1,693 @@
+// the xml is converted into the shape of Tiled's json export,
+// so both formats deserialize into the same TilemapData
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use serde_json::{self, Value, Map, Number};
+use xml::reader::{EventReader, XmlEvent};
+
+use super::TilemapData;
+
+struct Element {
+    name: String,
+    attrs: HashMap<String, String>,
+    children: Vec<Element>,
+    text: String,
+}
+
+impl Element {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs.get(name).map(|s| s.as_str())
+    }
+
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item=&'a Element> + 'a {
+        self.children.iter().filter(move |c| c.name == name)
+    }
+}
+
+pub fn load_tmx(path: &str) -> Result<TilemapData, String> {
+    let root = parse_file(path)?;
+    if root.name != "map" {
+        return Err(format!("Error while parsing tmx {}: root element is not a map", path));
+    }
+
+    let map = convert_map(&root, parent_dir(path))?;
+    match serde_json::from_value(map) {
+        Ok(data) => Ok(data),
+        Err(err) => Err(format!("Error while parsing tmx {}: {}", path, err))
+    }
+}
+
+fn parse_file(path: &str) -> Result<Element, String> {
+    match File::open(Path::new(&format!("assets/{}", path))) {
+        Ok(file) => parse_xml(BufReader::new(file), path),
+        Err(err) => Err(format!("Error while loading file {}: {}", path, err))
+    }
+}
+
+fn parse_xml<R: Read>(source: R, path: &str) -> Result<Element, String> {
+    let mut stack: Vec<Element> = Vec::new();
+    for event in EventReader::new(source) {
+        match event {
+            Ok(XmlEvent::StartElement { name, attributes, .. }) => {
+                let mut attrs = HashMap::new();
+                for a in attributes {
+                    attrs.insert(a.name.local_name, a.value);
+                }
+
+                stack.push(Element {
+                    name: name.local_name,
+                    attrs,
+                    children: Vec::new(),
+                    text: String::new()
+                });
+            },
+            Ok(XmlEvent::Characters(text)) | Ok(XmlEvent::CData(text)) => {
+                if let Some(el) = stack.last_mut() {
+                    el.text.push_str(&text);
+                }
+            },
+            Ok(XmlEvent::EndElement { .. }) => {
+                let el = stack.pop().unwrap();
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(el),
+                    None => return Ok(el)
+                }
+            },
+            Ok(_) => {},
+            Err(err) => return Err(format!("Error while parsing xml {}: {}", path, err))
+        }
+    }
+
+    Err(format!("Error while parsing xml {}: no root element", path))
+}
+
+fn convert_map(el: &Element, dir: String) -> Result<Value, String> {
+    let mut map = Map::new();
+    copy_int(&mut map, el, "width", 0);
+    copy_int(&mut map, el, "height", 0);
+    copy_int(&mut map, el, "tilewidth", 0);
+    copy_int(&mut map, el, "tileheight", 0);
+    copy_int(&mut map, el, "nextobjectid", 1);
+    copy_str(&mut map, el, "orientation", "orthogonal");
+    copy_str(&mut map, el, "renderorder", "right-down");
+    copy_bool(&mut map, el, "infinite", false);
+
+    // json exports carry the major version only
+    let version = el.attr("version").and_then(|v| v.parse::<f32>().ok()).unwrap_or(1.0);
+    map.insert(String::from("version"), Value::from(version as u64));
+    insert_properties(&mut map, el);
+
+    let mut tilesets = Vec::new();
+    for ts in el.children_named("tileset") {
+        tilesets.push(convert_tileset(ts, &dir)?);
+    }
+    map.insert(String::from("tilesets"), Value::Array(tilesets));
+
+    map.insert(String::from("layers"), Value::Array(convert_layers(el, el, &dir)));
+
+    Ok(Value::Object(map))
+}
+
+// the layers below the map or a group, in order
+fn convert_layers(parent: &Element, map: &Element, dir: &str) -> Vec<Value> {
+    let mut layers = Vec::new();
+    for child in &parent.children {
+        match child.name.as_str() {
+            "layer" => layers.push(convert_tile_layer(child, map.attr("infinite") == Some("1"))),
+            "objectgroup" => layers.push(convert_object_layer(child, map)),
+            "imagelayer" => layers.push(convert_image_layer(child, dir)),
+            "group" => {
+                let mut group = layer_common(child, "group");
+                group.insert(String::from("layers"), Value::Array(convert_layers(child, map, dir)));
+                layers.push(Value::Object(group));
+            },
+            _ => {}
+        }
+    }
+
+    layers
+}
+
+fn convert_tileset(el: &Element, dir: &str) -> Result<Value, String> {
+    let firstgid = el.attr("firstgid").and_then(|v| v.parse::<u64>().ok()).unwrap_or(1);
+
+    // external tilesets keep their image paths relative to the .tsx file
+    let tsx;
+    let (el, dir) = match el.attr("source") {
+        Some(source) => {
+            let path = join_path(dir, source);
+            tsx = parse_file(&path)?;
+            (&tsx, parent_dir(&path))
+        },
+        None => (el, String::from(dir))
+    };
+
+    let mut ts = Map::new();
+    ts.insert(String::from("firstgid"), Value::from(firstgid));
+    copy_str(&mut ts, el, "name", "");
+    copy_int(&mut ts, el, "tilewidth", 0);
+    copy_int(&mut ts, el, "tileheight", 0);
+    copy_int(&mut ts, el, "tilecount", 0);
+    copy_int(&mut ts, el, "columns", 0);
+    copy_int(&mut ts, el, "margin", 0);
+    copy_int(&mut ts, el, "spacing", 0);
+
+    if let Some(image) = el.child("image") {
+        let source = image.attr("source").unwrap_or("");
+        ts.insert(String::from("image"), Value::from(join_path(&dir, source)));
+        copy_int_as(&mut ts, image, "width", "imagewidth");
+        copy_int_as(&mut ts, image, "height", "imageheight");
+    }
+
+    insert_properties(&mut ts, el);
+
+    let mut tiles = Map::new();
+    let mut tileproperties = Map::new();
+    let mut tilepropertytypes = Map::new();
+    for tile in el.children_named("tile") {
+        let id = String::from(tile.attr("id").unwrap_or("0"));
+
+        let mut t = Map::new();
+        if let Some(image) = tile.child("image") {
+            let source = image.attr("source").unwrap_or("");
+            t.insert(String::from("image"), Value::from(join_path(&dir, source)));
+        }
+
+        if let Some(animation) = tile.child("animation") {
+            let mut frames = Vec::new();
+            for f in animation.children_named("frame") {
+                let mut frame = Map::new();
+                copy_int(&mut frame, f, "tileid", 0);
+                copy_int(&mut frame, f, "duration", 0);
+                frames.push(Value::Object(frame));
+            }
+            t.insert(String::from("animation"), Value::Array(frames));
+        }
+
+        if !t.is_empty() {
+            tiles.insert(id.clone(), Value::Object(t));
+        }
+
+        if let Some(props) = tile.child("properties") {
+            let (values, types) = convert_properties(props);
+            tileproperties.insert(id.clone(), values);
+            tilepropertytypes.insert(id, types);
+        }
+    }
+
+    if !tiles.is_empty() {
+        ts.insert(String::from("tiles"), Value::Object(tiles));
+    }
+
+    if !tileproperties.is_empty() {
+        ts.insert(String::from("tileproperties"), Value::Object(tileproperties));
+        ts.insert(String::from("tilepropertytypes"), Value::Object(tilepropertytypes));
+    }
+
+    Ok(Value::Object(ts))
+}
+
+fn convert_tile_layer(el: &Element, infinite: bool) -> Value {
+    let mut layer = layer_common(el, "tilelayer");
+    copy_int(&mut layer, el, "width", 0);
+    copy_int(&mut layer, el, "height", 0);
+
+    if let Some(data) = el.child("data") {
+        if let Some(encoding) = data.attr("encoding") {
+            layer.insert(String::from("encoding"), Value::from(encoding));
+        }
+
+        if let Some(compression) = data.attr("compression") {
+            layer.insert(String::from("compression"), Value::from(compression));
+        }
+
+        // infinite maps split the data into chunks
+        let chunks: Vec<Value> = data.children_named("chunk")
+            .map(|c| {
+                let mut chunk = Map::new();
+                copy_int(&mut chunk, c, "x", 0);
+                copy_int(&mut chunk, c, "y", 0);
+                copy_int(&mut chunk, c, "width", 0);
+                copy_int(&mut chunk, c, "height", 0);
+                chunk.insert(String::from("data"), convert_data(c, data.attr("encoding")));
+                Value::Object(chunk)
+            })
+            .collect();
+
+        // layers of infinite maps without any tiles have no chunks
+        if infinite {
+            layer.insert(String::from("chunks"), Value::Array(chunks));
+        } else {
+            layer.insert(String::from("data"), convert_data(data, data.attr("encoding")));
+        }
+    }
+
+    Value::Object(layer)
+}
+
+fn convert_data(el: &Element, encoding: Option<&str>) -> Value {
+    if encoding.is_some() {
+        return Value::from(el.text.trim());
+    }
+
+    // unencoded data lists one <tile> element per cell
+    let gids: Vec<Value> = el.children_named("tile")
+        .map(|t| t.attr("gid").and_then(|g| g.parse::<u64>().ok()).unwrap_or(0))
+        .map(Value::from)
+        .collect();
+    Value::Array(gids)
+}
+
+fn convert_image_layer(el: &Element, dir: &str) -> Value {
+    let mut layer = layer_common(el, "imagelayer");
+    copy_bool(&mut layer, el, "repeatx", false);
+    copy_bool(&mut layer, el, "repeaty", false);
+
+    let source = el.child("image").and_then(|i| i.attr("source")).unwrap_or("");
+    let image = if source.is_empty() { String::new() } else { join_path(dir, source) };
+    layer.insert(String::from("image"), Value::from(image));
+
+    Value::Object(layer)
+}
+
+fn convert_object_layer(el: &Element, map: &Element) -> Value {
+    let mut layer = layer_common(el, "objectgroup");
+
+    // object layers have no size in tmx, json exports use the map size
+    copy_int(&mut layer, map, "width", 0);
+    copy_int(&mut layer, map, "height", 0);
+    copy_str(&mut layer, el, "draworder", "topdown");
+
+    let mut objects = Vec::new();
+    for o in el.children_named("object") {
+        let mut object = Map::new();
+        copy_int(&mut object, o, "id", 0);
+        copy_float(&mut object, o, "x", 0.0);
+        copy_float(&mut object, o, "y", 0.0);
+        copy_float(&mut object, o, "width", 0.0);
+        copy_float(&mut object, o, "height", 0.0);
+        copy_float(&mut object, o, "rotation", 0.0);
+        copy_str(&mut object, o, "name", "");
+        copy_str(&mut object, o, "type", "");
+        copy_str(&mut object, o, "class", "");
+        copy_bool(&mut object, o, "visible", true);
+        if o.attr("gid").is_some() {
+            copy_int(&mut object, o, "gid", 0);
+        }
+
+        if o.child("point").is_some() {
+            object.insert(String::from("point"), Value::Bool(true));
+        }
+
+        if o.child("ellipse").is_some() {
+            object.insert(String::from("ellipse"), Value::Bool(true));
+        }
+
+        for shape in &["polygon", "polyline"] {
+            if let Some(el) = o.child(shape) {
+                object.insert(String::from(*shape), convert_points(el.attr("points").unwrap_or("")));
+            }
+        }
+
+        if let Some(text) = o.child("text") {
+            object.insert(String::from("text"), convert_text(text));
+        }
+
+        insert_properties(&mut object, o);
+        objects.push(Value::Object(object));
+    }
+
+    layer.insert(String::from("objects"), Value::Array(objects));
+    Value::Object(layer)
+}
+
+// "x,y x,y ..." into a list of points
+fn convert_points(points: &str) -> Value {
+    let coord = |v: Option<&str>| {
+        let v = v.and_then(|v| v.parse::<f64>().ok()).unwrap_or(0.0);
+        Number::from_f64(v).map(Value::Number).unwrap_or(Value::Null)
+    };
+
+    Value::Array(points.split_whitespace()
+        .map(|p| {
+            let mut xy = p.split(',');
+            let mut point = Map::new();
+            point.insert(String::from("x"), coord(xy.next()));
+            point.insert(String::from("y"), coord(xy.next()));
+            Value::Object(point)
+        })
+        .collect())
+}
+
+fn convert_text(el: &Element) -> Value {
+    let mut text = Map::new();
+    text.insert(String::from("text"), Value::from(el.text.as_str()));
+    copy_bool(&mut text, el, "wrap", false);
+    copy_bool(&mut text, el, "bold", false);
+    copy_bool(&mut text, el, "italic", false);
+    for name in &["color", "fontfamily", "halign", "valign"] {
+        if let Some(v) = el.attr(name) {
+            text.insert(String::from(*name), Value::from(v));
+        }
+    }
+    copy_int_as(&mut text, el, "pixelsize", "pixelsize");
+    Value::Object(text)
+}
+
+fn layer_common(el: &Element, layer_type: &str) -> Map<String, Value> {
+    let mut layer = Map::new();
+    layer.insert(String::from("type"), Value::from(layer_type));
+    copy_str(&mut layer, el, "name", "");
+    copy_float(&mut layer, el, "opacity", 1.0);
+    copy_bool(&mut layer, el, "visible", true);
+    copy_float(&mut layer, el, "offsetx", 0.0);
+    copy_float(&mut layer, el, "offsety", 0.0);
+    copy_float(&mut layer, el, "parallaxx", 1.0);
+    copy_float(&mut layer, el, "parallaxy", 1.0);
+    if let Some(tint) = el.attr("tintcolor") {
+        layer.insert(String::from("tintcolor"), Value::from(tint));
+    }
+    copy_int(&mut layer, el, "x", 0);
+    copy_int(&mut layer, el, "y", 0);
+    insert_properties(&mut layer, el);
+    layer
+}
+
+fn insert_properties(target: &mut Map<String, Value>, el: &Element) {
+    if let Some(props) = el.child("properties") {
+        let (values, types) = convert_properties(props);
+        target.insert(String::from("properties"), values);
+        target.insert(String::from("propertytypes"), types);
+    }
+}
+
+fn convert_properties(el: &Element) -> (Value, Value) {
+    let mut values = Map::new();
+    let mut types = Map::new();
+
+    for p in el.children_named("property") {
+        let name = String::from(p.attr("name").unwrap_or(""));
+        let prop_type = p.attr("type").unwrap_or("string");
+
+        // multiline string values are stored as element text
+        let raw = match p.attr("value") {
+            Some(v) => String::from(v),
+            None => p.text.clone()
+        };
+
+        let value = match prop_type {
+            "int" | "object" => raw.parse::<i64>().map(Value::from).unwrap_or(Value::Null),
+            "float" => raw.parse::<f64>().ok()
+                .and_then(Number::from_f64)
+                .map(Value::Number)
+                .unwrap_or(Value::Null),
+            "bool" => Value::Bool(raw == "true"),
+            _ => Value::String(raw)
+        };
+
+        values.insert(name.clone(), value);
+        types.insert(name, Value::from(prop_type));
+    }
+
+    (Value::Object(values), Value::Object(types))
+}
+
+fn copy_str(target: &mut Map<String, Value>, el: &Element, name: &str, default: &str) {
+    let v = el.attr(name).unwrap_or(default);
+    target.insert(String::from(name), Value::from(v));
+}
+
+fn copy_int(target: &mut Map<String, Value>, el: &Element, name: &str, default: i64) {
+    copy_int_as(target, el, name, name);
+    if !target.contains_key(name) {
+        target.insert(String::from(name), Value::from(default));
+    }
+}
+
+fn copy_int_as(target: &mut Map<String, Value>, el: &Element, name: &str, key: &str) {
+    if let Some(v) = el.attr(name).and_then(|v| v.parse::<i64>().ok()) {
+        target.insert(String::from(key), Value::from(v));
+    }
+}
+
+fn copy_float(target: &mut Map<String, Value>, el: &Element, name: &str, default: f64) {
+    let v = el.attr(name).and_then(|v| v.parse::<f64>().ok()).unwrap_or(default);
+    let v = Number::from_f64(v).map(Value::Number).unwrap_or(Value::Null);
+    target.insert(String::from(name), v);
+}
+
+fn copy_bool(target: &mut Map<String, Value>, el: &Element, name: &str, default: bool) {
+    // tmx writes booleans as 0 and 1
+    let v = match el.attr(name) {
+        Some(v) => v == "1" || v == "true",
+        None => default
+    };
+    target.insert(String::from(name), Value::Bool(v));
+}
+
+fn parent_dir(path: &str) -> String {
+    match path.rfind('/') {
+        Some(i) => String::from(&path[..i]),
+        None => String::new()
+    }
+}
+
+// resolves . and .. where possible
+fn join_path(dir: &str, rel: &str) -> String {
+    let mut parts: Vec<&str> = dir.split('/').filter(|p| !p.is_empty()).collect();
+
+    for part in rel.split('/') {
+        match part {
+            "" | "." => {},
+            ".." => {
+                if parts.is_empty() || *parts.last().unwrap() == ".." {
+                    parts.push("..");
+                } else {
+                    parts.pop();
+                }
+            },
+            p => parts.push(p)
+        }
+    }
+
+    parts.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use types::{Size, Rect};
+
+    use super::super::{TilemapData, CollisionMap};
+    use super::super::layer::LayerData;
+    use super::super::object::{ObjectKind, MapObject};
+    use super::{parse_file, parse_xml, convert_map};
+
+    fn load(path: &str) -> TilemapData {
+        let root = parse_file(path).unwrap();
+        serde_json::from_value(convert_map(&root, String::new()).unwrap()).unwrap()
+    }
+
+    fn load_str(xml: &str) -> TilemapData {
+        let root = parse_xml(xml.as_bytes(), "inline.tmx").unwrap();
+        serde_json::from_value(convert_map(&root, String::new()).unwrap()).unwrap()
+    }
+
+    // cells of a finite layer
+    fn layer_gids(layer: &LayerData) -> Vec<u32> {
+        layer.decode_blocks().unwrap().remove(0).gids
+    }
+
+    #[test]
+    fn shipped_maps() {
+        let map = load("tilemap-small-0.tmx");
+        assert_eq!(map.properties().get_int("entitylayer"), Some(4));
+        assert_eq!(map.entity_layer(), 4);
+
+        let names: Vec<&str> = map.layers.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, vec!["ground", "terrain-bg2", "terrain-bg1", "terrain-bg0", "entities",
+                               "terrain-fg0", "terrain-fg1", "terrain-fg2"]);
+
+        let gids = layer_gids(&map.layers[2]);
+        assert_eq!(gids.len(), 400);
+        assert_eq!(&gids[..3], &[187, 187, 187]);
+        assert_eq!(gids.iter().filter(|&&g| g != 0).count(), 108);
+
+        let objects = map.layers[4].objects();
+        assert_eq!(objects.len(), 8);
+        assert_eq!(MapObject::new(&objects[1]).id, 3);
+        match objects[1].kind() {
+            ObjectKind::Tile(gid) => assert_eq!(gid, 818),
+            kind => panic!("expected a tile object, got {:?}", kind)
+        }
+        // tile objects are anchored at their bottom left corner
+        let b = MapObject::new(&objects[1]).bounds();
+        assert_eq!((b.pos.x, b.pos.y, b.size.w, b.size.h), (205.5, 37.5, 16.0, 27.0));
+
+        // entities go above the last background layer
+        assert_eq!(load("testmap.tmx").entity_layer(), 1);
+
+        let big = load("tilemap-big-0.tmx");
+        assert_eq!(big.cell_bounds(), (0, 0, 100, 100));
+        assert_eq!(layer_gids(&big.layers[1]).iter().filter(|&&g| g != 0).count(), 452);
+    }
+
+    #[test]
+    fn shipped_tilesets() {
+        let root = parse_file("tilemap-small-0.tmx").unwrap();
+        let map = convert_map(&root, String::new()).unwrap();
+        let tilesets = map.get("tilesets").and_then(|t| t.as_array()).unwrap();
+
+        assert_eq!(tilesets[0].get("image").and_then(|i| i.as_str()), Some("tileset.png"));
+        assert_eq!(tilesets[0].get("imageheight").and_then(|i| i.as_u64()), Some(816));
+        assert_eq!(tilesets[1].get("firstgid").and_then(|i| i.as_u64()), Some(817));
+        assert!(tilesets[1].get("image").is_none());
+
+        let tile = tilesets[1].get("tiles").and_then(|t| t.get("1")).unwrap();
+        assert_eq!(tile.get("image").and_then(|i| i.as_str()),
+                   Some("../../sprites/pokemon tilemaps/tree-small0.png"));
+    }
+
+    #[test]
+    fn external_tileset() {
+        let map = convert_map(&parse_xml(r#"<?xml version="1.0" encoding="UTF-8"?>
+            <map version="1.2" orientation="orthogonal" renderorder="right-down"
+                 width="1" height="1" tilewidth="16" tileheight="16" nextobjectid="1">
+             <tileset firstgid="5" source="tilesets/test.tsx"/>
+            </map>"#.as_bytes(), "inline.tmx").unwrap(), String::new()).unwrap();
+        let ts = &map.get("tilesets").and_then(|t| t.as_array()).unwrap()[0];
+
+        // the firstgid comes from the map, everything else from the .tsx
+        assert_eq!(ts.get("firstgid").and_then(|v| v.as_u64()), Some(5));
+        assert_eq!(ts.get("name").and_then(|v| v.as_str()), Some("external"));
+        assert_eq!(ts.get("columns").and_then(|v| v.as_u64()), Some(16));
+        assert_eq!(ts.get("properties").and_then(|p| p.get("biome")).and_then(|v| v.as_str()), Some("forest"));
+
+        // image paths are relative to the .tsx
+        assert_eq!(ts.get("image").and_then(|v| v.as_str()), Some("tileset.png"));
+        let tile = ts.get("tiles").and_then(|t| t.get("3")).unwrap();
+        assert_eq!(tile.get("image").and_then(|v| v.as_str()), Some("tilesets/extra/rock.png"));
+    }
+
+    #[test]
+    fn encodings_groups_and_properties() {
+        let xml = r##"<?xml version="1.0" encoding="UTF-8"?>
+            <map version="1.2" orientation="orthogonal" renderorder="right-down"
+                 width="2" height="2" tilewidth="16" tileheight="16" nextobjectid="3">
+             <properties>
+              <property name="title" value="test"/>
+              <property name="entitylayer" type="int" value="2"/>
+              <property name="scale" type="float" value="1.5"/>
+              <property name="tint" type="color" value="#ff102030"/>
+             </properties>
+             <tileset firstgid="1" name="ts" tilewidth="16" tileheight="16" tilecount="4" columns="2">
+              <image source="tileset.png" width="32" height="32"/>
+             </tileset>
+             <layer name="csv" width="2" height="2" x="3" y="1">
+              <data encoding="csv">1,2,
+              3,4</data>
+             </layer>
+             <layer name="xml" width="2" height="2">
+              <data><tile gid="4"/><tile/><tile gid="2"/><tile gid="1"/></data>
+             </layer>
+             <group name="group" offsetx="8" opacity="0.5">
+              <layer name="zlib" width="2" height="2" offsety="4">
+               <data encoding="base64" compression="zlib">eJxjZGBgYAJiZiBmYWBoAAAA4ACL</data>
+              </layer>
+              <objectgroup name="things" draworder="index">
+               <object id="1" name="sign" type="text" x="4" y="6" width="20" height="10">
+                <properties><property name="target" type="object" value="2"/></properties>
+                <text wrap="1" color="#00ff00" fontfamily="serif" pixelsize="12"
+                      halign="center" valign="bottom" bold="1">Hello</text>
+               </object>
+               <object id="2" x="0" y="0"><polygon points="0,0 8,0 8,-4"/></object>
+              </objectgroup>
+             </group>
+            </map>"##;
+
+        let map = load_str(xml);
+        // entitylayer counts top level layers, the group starts at its first child
+        assert_eq!(map.entity_layer(), 2);
+
+        let props = map.properties();
+        assert_eq!(props.get_str("title"), Some("test"));
+        assert_eq!(props.get_float("scale"), Some(1.5));
+        assert_eq!(props.get_color("tint").map(|c| c.rgba()), Some((0x10, 0x20, 0x30, 0xff)));
+
+        assert_eq!(layer_gids(&map.layers[0]), vec![1, 2, 3, 4]);
+        assert_eq!(map.layers[0].cell_bounds(), Some((3, 1, 5, 3)));
+        assert_eq!(layer_gids(&map.layers[1]), vec![4, 0, 2, 1]);
+
+        let flat = map.flat_layers();
+        assert_eq!(flat.len(), 4);
+        assert_eq!(flat[2].data.name, "zlib");
+        // flip flags are kept for the tiles to pick up
+        assert_eq!(layer_gids(flat[2].data), vec![1, 2, 3, 0x80000004]);
+        assert_eq!((flat[2].style.offset.x, flat[2].style.offset.y), (8.0, 4.0));
+        assert_eq!(flat[2].style.alpha, 128);
+
+        let objects = flat[3].data.objects();
+        assert_eq!(objects[0].properties().get_object("target"), Some(2));
+        match objects[0].kind() {
+            ObjectKind::Text(ref text) => {
+                assert_eq!(text.text, "Hello");
+                assert!(text.wrap && text.bold && !text.italic);
+                assert_eq!(text.color.rgba(), (0, 0xff, 0, 0xff));
+                assert_eq!((text.font_family.as_str(), text.pixel_size), ("serif", 12));
+                assert_eq!((text.halign.as_str(), text.valign.as_str()), ("center", "bottom"));
+            },
+            kind => panic!("expected a text object, got {:?}", kind)
+        }
+        match objects[1].kind() {
+            ObjectKind::Polygon(ref points) => assert_eq!(points.len(), 3),
+            kind => panic!("expected a polygon, got {:?}", kind)
+        }
+    }
+
+    #[test]
+    fn infinite_chunks() {
+        let map = load_str(r#"<?xml version="1.0" encoding="UTF-8"?>
+            <map version="1.2" orientation="orthogonal" renderorder="right-down" width="30" height="20"
+                 tilewidth="16" tileheight="16" infinite="1" nextobjectid="1">
+             <layer name="ground" width="30" height="20">
+              <data encoding="csv">
+               <chunk x="-16" y="-16" width="2" height="2">1,0,0,2</chunk>
+               <chunk x="100000" y="0" width="2" height="1">3,3</chunk>
+              </data>
+             </layer>
+             <layer name="empty" width="30" height="20">
+              <data encoding="csv"/>
+             </layer>
+            </map>"#);
+
+        // far apart chunks stay separate blocks
+        let flat = map.flat_layers();
+        let blocks = flat[0].data.decode_blocks().unwrap();
+        assert_eq!(blocks.len(), 2);
+        let cells: Vec<(i32, i32, u32)> = blocks[0].cells().filter(|c| c.2 != 0).collect();
+        assert_eq!(cells, vec![(-16, -16, 1), (-15, -15, 2)]);
+        assert_eq!(map.layers[0].cell_bounds(), Some((-16, -16, 100002, 1)));
+
+        assert!(map.layers[1].decode_blocks().unwrap().is_empty());
+        assert_eq!(map.layers[1].cell_bounds(), None);
+        assert_eq!(map.cell_bounds(), (-16, -16, 100002, 1));
+
+        let mut collision = CollisionMap::new(map.cell_bounds(), Size::new(16.0, 16.0));
+        collision.add_tile_layer(&flat[0], &blocks, &[]);
+        assert!(!collision.is_solid(-16, -16));
+        collision.set_solid(100001, 0);
+        assert!(collision.is_solid(100001, 0));
+        assert!(!collision.is_solid(0, 0));
+        assert!(collision.is_solid(-17, 0));
+        assert!(collision.is_blocked(&Rect::new(100001.0 * 16.0, 0.0, 8.0, 8.0)));
+    }
+}
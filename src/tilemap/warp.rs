@@ -0,0 +1,78 @@
+use types::{Rect, Direction};
+
+use super::object::MapObject;
+
+// a warp object moves the player onto the object named by spawn on the
+// registered map named by map, turned towards the optional facing
+#[derive(Clone)]
+pub struct Warp {
+    pub area: Rect,
+    pub map: String,
+    pub spawn: String,
+    pub facing: Option<Direction>,
+}
+
+impl Warp {
+    pub fn from_object(o: &MapObject) -> Option<Warp> {
+        if o.object_type != "warp" { return None; }
+
+        let (map, spawn) = match (o.properties.get_str("map"), o.properties.get_str("spawn")) {
+            (Some(map), Some(spawn)) => (map, spawn),
+            _ => {
+                println!("warp {} needs a map and a spawn property", o.id);
+                return None;
+            }
+        };
+
+        // the trigger area is an axis aligned box
+        if o.rotation != 0.0 {
+            println!("warp {} can't be rotated", o.id);
+            return None;
+        }
+
+        let facing = o.properties.get_str("facing").and_then(Direction::from_name);
+
+        Some(Warp {
+            area: o.bounds(),
+            map: String::from(map),
+            spawn: String::from(spawn),
+            facing
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use types::Direction;
+    use super::super::object::{ObjectData, MapObject};
+    use super::Warp;
+
+    fn object(object_type: &str, extra: &str) -> Option<Warp> {
+        let data: ObjectData = serde_json::from_str(&format!(r#"{{
+            "id": 1, "type": "{}", "x": 32, "y": 16, "width": 16, "height": 8,
+            "visible": true{}
+        }}"#, object_type, extra)).unwrap();
+        Warp::from_object(&MapObject::new(&data))
+    }
+
+    fn warp(extra: &str) -> Option<Warp> {
+        object("warp", extra)
+    }
+
+    #[test]
+    fn from_object() {
+        let w = warp(r#", "properties": { "map": "big", "spawn": "door", "facing": "up" }"#).unwrap();
+        assert_eq!((w.map.as_str(), w.spawn.as_str()), ("big", "door"));
+        assert_eq!((w.area.pos.x, w.area.pos.y, w.area.size.w, w.area.size.h), (32.0, 16.0, 16.0, 8.0));
+        assert!(w.facing == Some(Direction::Up));
+
+        let w = warp(r#", "properties": { "map": "big", "spawn": "door", "facing": "north" }"#).unwrap();
+        assert!(w.facing.is_none());
+        assert!(warp(r#", "properties": { "map": "big" }"#).is_none());
+        assert!(warp(r#", "properties": { "spawn": "door" }"#).is_none());
+        assert!(warp(r#", "rotation": 90, "properties": { "map": "big", "spawn": "door" }"#).is_none());
+        assert!(object("door", r#", "properties": { "map": "big", "spawn": "door" }"#).is_none());
+    }
+}
@@ -1,5 +1,6 @@
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::cmp::Ordering;
 
 use sdl2::rect::Rect as SdlRect;
 
@@ -13,10 +14,67 @@ pub type Vec2 = super::cgmath::Vector2<f32>;
 pub struct RenderInfo {
     pub pos: Point,
     pub size: Size,
-    pub z: f32,
+    pub depth: Depth,
+    pub transform: Transform,
+    pub alpha: u8,
+    // multiplied with the texture's colors
+    pub color_mod: Color,
+    pub blend: Blend,
+    // how fast it scrolls with the camera, 1 moves with the world
+    pub parallax: Vec2,
     pub renderable: Renderable,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Blend {
+    Alpha,
+    Additive,
+    Multiply,
+}
+
+// layers draw in order, within a layer by bottom edge y, then by seq
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Depth {
+    pub layer: u32,
+    pub y: f32,
+    pub seq: u32,
+}
+
+impl Depth {
+    pub fn new(layer: u32, y: f32) -> Depth {
+        Depth { layer, y, seq: 0 }
+    }
+
+    // below everything of the layer that is sorted by y
+    pub fn in_order(layer: u32, seq: u32) -> Depth {
+        Depth { layer, y: f32::NEG_INFINITY, seq }
+    }
+
+    pub fn cmp(&self, other: &Depth) -> Ordering {
+        self.layer.cmp(&other.layer)
+            .then(self.y.total_cmp(&other.y))
+            .then(self.seq.cmp(&other.seq))
+    }
+}
+
+// clockwise degrees around the center, applied after flipping
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub angle: f64,
+    pub flip_h: bool,
+    pub flip_v: bool,
+}
+
+impl Transform {
+    pub fn identity() -> Transform {
+        Transform { angle: 0.0, flip_h: false, flip_v: false }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.angle == 0.0 && !self.flip_h && !self.flip_v
+    }
+}
+
 #[derive(Clone)]
 pub enum Renderable {
     //TODO figure out if this has a performance impact using RcRefCell
@@ -28,26 +86,108 @@ pub enum Renderable {
 impl RenderInfo {
     pub fn texture(pos: Point, size: Size,
                    src: Point, src_size: Size,
-                   z: f32, tex: Rc<RefCell<Texture>>) -> RenderInfo {
+                   depth: Depth, tex: Rc<RefCell<Texture>>) -> RenderInfo {
         RenderInfo {
             pos: pos,
             size: size,
-            z: z,
+            depth,
+            transform: Transform::identity(),
+            alpha: 255,
+            color_mod: Color::RGB(255, 255, 255),
+            blend: Blend::Alpha,
+            parallax: Vec2::new(1.0, 1.0),
             renderable: Renderable::Texture { src: src, src_size: src_size, tex: tex }
         }
     }
 
-    pub fn rect(pos: Point, size: Size, z: f32, color: Color) -> RenderInfo {
+    pub fn rect(pos: Point, size: Size, depth: Depth, color: Color) -> RenderInfo {
         RenderInfo {
             pos: pos,
             size: size,
-            z: z,
+            depth,
+            transform: Transform::identity(),
+            alpha: 255,
+            color_mod: Color::RGB(255, 255, 255),
+            blend: Blend::Alpha,
+            parallax: Vec2::new(1.0, 1.0),
             renderable: Renderable::Rect { color: color }
         }
     }
+
+    pub fn transformed(mut self, transform: Transform) -> RenderInfo {
+        self.transform = transform;
+        self
+    }
+
+    // multiplies alpha, color mod and offset, sets the blend mode
+    pub fn styled(mut self, style: &RenderStyle) -> RenderInfo {
+        self.pos += style.offset;
+        self.alpha = mul_u8(self.alpha, style.alpha);
+        self.color_mod = mul_color(self.color_mod, style.color_mod);
+        self.blend = style.blend;
+        self.parallax = mul_vec(self.parallax, style.parallax);
+        self
+    }
+
+    // screen space, grown to cover any rotation
+    pub fn bounds(&self) -> Rect {
+        if self.transform.angle == 0.0 {
+            return Rect { pos: self.pos, size: self.size };
+        }
+
+        let extent = (self.size.w * self.size.w + self.size.h * self.size.h).sqrt();
+        let center = Point::new(self.pos.x + self.size.w / 2.0, self.pos.y + self.size.h / 2.0);
+        Rect::new(center.x - extent / 2.0, center.y - extent / 2.0, extent, extent)
+    }
+}
+
+// shared by everything on a layer
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RenderStyle {
+    pub alpha: u8,
+    pub color_mod: Color,
+    pub blend: Blend,
+    pub offset: Vec2,
+    pub parallax: Vec2,
+}
+
+impl RenderStyle {
+    pub fn new() -> RenderStyle {
+        RenderStyle {
+            alpha: 255,
+            color_mod: Color::RGB(255, 255, 255),
+            blend: Blend::Alpha,
+            offset: Vec2::new(0.0, 0.0),
+            parallax: Vec2::new(1.0, 1.0)
+        }
+    }
+
+    // for layers inside a group
+    pub fn within(&self, parent: &RenderStyle) -> RenderStyle {
+        RenderStyle {
+            alpha: mul_u8(self.alpha, parent.alpha),
+            color_mod: mul_color(self.color_mod, parent.color_mod),
+            blend: self.blend,
+            offset: self.offset + parent.offset,
+            parallax: mul_vec(self.parallax, parent.parallax)
+        }
+    }
+}
+
+fn mul_u8(a: u8, b: u8) -> u8 {
+    ((a as u16 * b as u16) / 255) as u8
+}
+
+fn mul_vec(a: Vec2, b: Vec2) -> Vec2 {
+    Vec2::new(a.x * b.x, a.y * b.y)
 }
 
-#[derive(Copy, Clone)]
+fn mul_color(a: Color, b: Color) -> Color {
+    let ((r0, g0, b0), (r1, g1, b1)) = (a.rgb(), b.rgb());
+    Color::RGB(mul_u8(r0, r1), mul_u8(g0, g1), mul_u8(b0, b1))
+}
+
+#[derive(Copy, Clone, PartialEq)]
 pub enum Direction {
     Down,
     Up,
@@ -55,6 +195,27 @@ pub enum Direction {
     Right
 }
 
+impl Direction {
+    pub fn from_name(name: &str) -> Option<Direction> {
+        match name {
+            "down" => Some(Direction::Down),
+            "up" => Some(Direction::Up),
+            "left" => Some(Direction::Left),
+            "right" => Some(Direction::Right),
+            _ => None
+        }
+    }
+
+    pub fn unit(&self) -> Vec2 {
+        match *self {
+            Direction::Down => Vec2::new(0.0, 1.0),
+            Direction::Up => Vec2::new(0.0, -1.0),
+            Direction::Left => Vec2::new(-1.0, 0.0),
+            Direction::Right => Vec2::new(1.0, 0.0)
+        }
+    }
+}
+
 pub fn to_sdl_rect(p: Point, s: Size) -> SdlRect {
     SdlRect::new(p.x as i32, p.y as i32, s.w as u32, s.h as u32)
 }
@@ -67,6 +228,35 @@ pub enum KeyAction {
     Right,
 }
 
+#[derive(Copy, Clone, Debug)]
+pub struct Rect {
+    pub pos: Point,
+    pub size: Size,
+}
+
+impl Rect {
+    pub fn new(x: f32, y: f32, w: f32, h: f32) -> Rect {
+        Rect { pos: Point::new(x, y), size: Size::new(w, h) }
+    }
+
+    pub fn left(&self) -> f32 { self.pos.x }
+    pub fn top(&self) -> f32 { self.pos.y }
+    pub fn right(&self) -> f32 { self.pos.x + self.size.w }
+    pub fn bottom(&self) -> f32 { self.pos.y + self.size.h }
+    pub fn center(&self) -> Point {
+        Point::new(self.pos.x + self.size.w / 2.0, self.pos.y + self.size.h / 2.0)
+    }
+
+    pub fn offset(&self, p: Point) -> Rect {
+        Rect::new(self.pos.x + p.x, self.pos.y + p.y, self.size.w, self.size.h)
+    }
+
+    pub fn intersects(&self, other: &Rect) -> bool {
+        self.left() < other.right() && other.left() < self.right() &&
+            self.top() < other.bottom() && other.top() < self.bottom()
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct Size {
     pub w: f32,